@@ -0,0 +1,122 @@
+//! Minimal ACPI table discovery.
+//!
+//! Locates the RSDP in the BIOS areas, then walks the RSDT or XSDT to find
+//! system description tables by signature. Tables are read through the
+//! physical memory mapping, see [`memory::phys_to_virt`].
+
+use crate::memory;
+use conquer_once::spin::OnceCell;
+use core::{mem::size_of, slice};
+use x86_64::PhysAddr;
+
+static ROOT: OnceCell<Option<RootTable>> = OnceCell::uninit();
+
+/// Header shared by all system description tables.
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+#[allow(dead_code)]
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    // ACPI 2.0+
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+/// The RSDT holds 32-bit table pointers, the XSDT 64-bit ones.
+#[derive(Debug, Clone, Copy)]
+struct RootTable {
+    addr: PhysAddr,
+    entry_size: usize,
+}
+
+/// Finds the table with the given signature, e.g. `b"HPET"` or `b"APIC"`,
+/// and returns its physical address.
+pub fn find_table(signature: &[u8; 4]) -> Option<PhysAddr> {
+    let root = (*ROOT.get_or_init(find_root))?;
+    let header = unsafe { read_header(root.addr) };
+    let entries = (header.length as usize - size_of::<SdtHeader>()) / root.entry_size;
+    let first_entry = root.addr + size_of::<SdtHeader>();
+
+    (0..entries)
+        .map(|i| {
+            let entry = memory::phys_to_virt(first_entry + (i * root.entry_size) as u64);
+            let addr = unsafe {
+                match root.entry_size {
+                    4 => entry.as_ptr::<u32>().read_unaligned() as u64,
+                    _ => entry.as_ptr::<u64>().read_unaligned(),
+                }
+            };
+            PhysAddr::new(addr)
+        })
+        .find(|&table| unsafe { &read_header(table).signature == signature } && checksum_ok(table))
+}
+
+/// Reads the header of the table at `addr`.
+///
+/// # Safety
+///
+/// `addr` must point to a system description table.
+pub unsafe fn read_header(addr: PhysAddr) -> SdtHeader {
+    unsafe { memory::phys_to_virt(addr).as_ptr::<SdtHeader>().read_unaligned() }
+}
+
+fn checksum_ok(table: PhysAddr) -> bool {
+    let length = unsafe { read_header(table).length };
+    checksum(table, length as usize)
+}
+
+fn checksum(addr: PhysAddr, len: usize) -> bool {
+    let bytes = unsafe { slice::from_raw_parts(memory::phys_to_virt(addr).as_ptr::<u8>(), len) };
+    bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+}
+
+fn find_root() -> Option<RootTable> {
+    let rsdp_addr = find_rsdp()?;
+    let rsdp = unsafe { memory::phys_to_virt(rsdp_addr).as_ptr::<Rsdp>().read_unaligned() };
+
+    if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+        let addr = PhysAddr::new(rsdp.xsdt_address);
+        if checksum_ok(addr) {
+            return Some(RootTable { addr, entry_size: 8 });
+        }
+    }
+    let addr = PhysAddr::new(rsdp.rsdt_address as u64);
+    checksum_ok(addr).then_some(RootTable { addr, entry_size: 4 })
+}
+
+/// Searches the first KiB of the EBDA and the BIOS ROM area for the RSDP.
+fn find_rsdp() -> Option<PhysAddr> {
+    let ebda_segment =
+        unsafe { memory::phys_to_virt(PhysAddr::new(0x40E)).as_ptr::<u16>().read_unaligned() };
+    let ebda = ebda_segment as u64 * 16;
+
+    let ebda_area = (ebda..ebda + 1024).step_by(16);
+    let bios_area = (0xE0000..0x100000).step_by(16);
+    ebda_area
+        .chain(bios_area)
+        .map(PhysAddr::new)
+        .find(|&addr| {
+            let signature =
+                unsafe { memory::phys_to_virt(addr).as_ptr::<[u8; 8]>().read_unaligned() };
+            &signature == b"RSD PTR " && checksum(addr, 20)
+        })
+}
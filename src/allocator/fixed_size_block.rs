@@ -4,6 +4,7 @@ use core::{
     mem,
     ptr::{self, NonNull},
 };
+use x86_64::instructions::interrupts::without_interrupts;
 
 /// The block sizes to use.
 ///
@@ -58,8 +59,20 @@ impl FixedSizeBlockAllocator {
     }
 }
 
+// Interrupts are disabled while the allocator is locked, so that interrupt
+// handlers (e.g. spawning a task) can allocate without deadlocking.
 unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        without_interrupts(|| unsafe { self.alloc_locked(layout) })
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        without_interrupts(|| unsafe { self.dealloc_locked(ptr, layout) })
+    }
+}
+
+impl Locked<FixedSizeBlockAllocator> {
+    unsafe fn alloc_locked(&self, layout: Layout) -> *mut u8 {
         let mut allocator = self.lock();
         match list_index(&layout) {
             Some(index) => {
@@ -82,7 +95,7 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
         }
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    unsafe fn dealloc_locked(&self, ptr: *mut u8, layout: Layout) {
         let mut allocator = self.lock();
         match list_index(&layout) {
             Some(index) => {
@@ -0,0 +1,270 @@
+//! Frame-pointer based stack unwinding.
+//!
+//! The target spec keeps frame pointers, so every frame starts with the saved
+//! `rbp` of the caller followed by the return address. Addresses are resolved
+//! through the symbol table of the kernel ELF file itself.
+
+use crate::memory;
+use crate::{println, serial_println};
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use conquer_once::spin::OnceCell;
+use core::arch::asm;
+use core::fmt;
+use x86_64::PhysAddr;
+use x86_64::structures::idt::InterruptStackFrame;
+
+/// Upper bound on printed frames, protects against corrupted chains.
+const MAX_FRAMES: usize = 32;
+
+/// Where the bootloader loads the kernel ELF file. The file stays there as
+/// a `Kernel` memory region after its segments are mapped, and only its
+/// debug info is stripped, so the symbol table the linker produced is
+/// still in it.
+const KERNEL_ELF: u64 = 0x40_0000;
+
+const SHT_SYMTAB: u32 = 2;
+const STT_FUNC: u8 = 2;
+const SYMBOL_SIZE: usize = 24;
+
+static SYMBOLS: OnceCell<SymbolTable> = OnceCell::uninit();
+
+/// The `.symtab` and `.strtab` sections of the kernel ELF.
+struct SymbolTable {
+    symbols: &'static [u8],
+    names: &'static [u8],
+}
+
+/// A function name as found in the symbol table, displayed demangled.
+#[derive(Debug, Clone, Copy)]
+pub struct Symbol(&'static str);
+
+impl Symbol {
+    /// Stands in for addresses that do not resolve.
+    pub const UNKNOWN: Symbol = Symbol("<unknown>");
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // the alternate form leaves out the hash
+        write!(f, "{:#}", rustc_demangle::demangle(self.0))
+    }
+}
+
+/// Finds the symbol table of the kernel in memory. Until then, and if it
+/// cannot be found, backtraces show raw addresses.
+///
+/// Needs the physical memory mapping of [`memory::init`].
+pub fn init(memory_map: &MemoryMap) {
+    let Some(region) = memory_map.iter().find(|region| {
+        region.region_type == MemoryRegionType::Kernel
+            && (region.range.start_addr()..region.range.end_addr()).contains(&KERNEL_ELF)
+    }) else {
+        return;
+    };
+    let start = memory::phys_to_virt(PhysAddr::new(KERNEL_ELF));
+    let len = (region.range.end_addr() - KERNEL_ELF) as usize;
+    let elf = unsafe { core::slice::from_raw_parts(start.as_ptr::<u8>(), len) };
+    if let Some(table) = SymbolTable::parse(elf) {
+        SYMBOLS.init_once(|| table);
+    }
+}
+
+/// Prints a backtrace of the caller to VGA and serial.
+#[inline(never)]
+pub fn print() {
+    print_header();
+    walk(current_frame(), None, print_frame);
+}
+
+/// Prints a backtrace of the code interrupted by an exception, starting at
+/// the faulting instruction.
+///
+/// Must be called directly from the interrupt handler that owns `stack_frame`.
+#[inline(never)]
+pub fn print_interrupted(stack_frame: &InterruptStackFrame) {
+    print_header();
+    print_frame(stack_frame.instruction_pointer.as_u64());
+    walk(current_frame(), Some(stack_frame), print_frame);
+}
+
+/// Calls `f` with the return address of every frame reachable from `rbp`.
+///
+/// If `interrupted` is given, the frames of the interrupt handler and
+/// everything above it are skipped, and the walk continues in the
+/// interrupted code instead.
+pub fn walk(mut rbp: u64, interrupted: Option<&InterruptStackFrame>, mut f: impl FnMut(u64)) {
+    let handler_frame = interrupted.map(|frame| frame as *const InterruptStackFrame as u64);
+    let mut skipping = handler_frame.is_some();
+
+    for _ in 0..MAX_FRAMES {
+        if rbp == 0 || !rbp.is_multiple_of(8) {
+            break;
+        }
+        let (saved_rbp, return_address) = unsafe {
+            let frame = rbp as *const u64;
+            (*frame, *frame.add(1))
+        };
+        if skipping {
+            // the handler saves `rbp` right below the CPU pushed frame, or
+            // below the error code if there is one
+            if let Some(handler_frame) = handler_frame
+                && (rbp + 8 == handler_frame || rbp + 16 == handler_frame)
+            {
+                skipping = false;
+            }
+        } else if return_address == 0 {
+            break;
+        } else {
+            f(return_address);
+        }
+        // stacks grow down, so the caller's frame must be above ours
+        if saved_rbp <= rbp {
+            break;
+        }
+        rbp = saved_rbp;
+    }
+}
+
+/// Resolves `addr` to the enclosing function and the offset into it.
+pub fn resolve(addr: u64) -> Option<(Symbol, u64)> {
+    SYMBOLS.try_get().ok()?.resolve(addr)
+}
+
+#[inline(always)]
+fn current_frame() -> u64 {
+    let rbp: u64;
+    unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+    rbp
+}
+
+fn print_header() {
+    println!("Backtrace:");
+    serial_println!("Backtrace:");
+}
+
+fn print_frame(addr: u64) {
+    match resolve(addr) {
+        Some((name, offset)) => {
+            println!("  {:#x} {}+{:#x}", addr, name, offset);
+            serial_println!("  {:#x} {}+{:#x}", addr, name, offset);
+        }
+        None => {
+            println!("  {:#x} <unknown>", addr);
+            serial_println!("  {:#x} <unknown>", addr);
+        }
+    }
+}
+
+impl SymbolTable {
+    fn parse(elf: &'static [u8]) -> Option<Self> {
+        if elf.get(..5)? != b"\x7fELF\x02" {
+            return None;
+        }
+        let section_headers = read_u64(elf, 0x28)? as usize;
+        let header_size = usize::from(read_u16(elf, 0x3A)?);
+        let section_count = usize::from(read_u16(elf, 0x3C)?);
+        let section = |index: usize| {
+            let header = section_headers + index * header_size;
+            let offset = read_u64(elf, header + 0x18)? as usize;
+            let size = read_u64(elf, header + 0x20)? as usize;
+            Some((header, elf.get(offset..offset + size)?))
+        };
+
+        let (symtab, symbols) = (0..section_count)
+            .filter_map(section)
+            .find(|&(header, _)| read_u32(elf, header + 4) == Some(SHT_SYMTAB))?;
+        let (_, names) = section(read_u32(elf, symtab + 0x28)? as usize)?;
+        Some(SymbolTable { symbols, names })
+    }
+
+    /// Finds the function containing `addr`, or the closest one before it
+    /// for symbols without a size.
+    fn resolve(&self, addr: u64) -> Option<(Symbol, u64)> {
+        let mut closest: Option<(u64, u32)> = None;
+        for symbol in self.symbols.chunks_exact(SYMBOL_SIZE) {
+            let start = read_u64(symbol, 8)?;
+            let size = read_u64(symbol, 16)?;
+            if symbol[4] & 0xF != STT_FUNC || start == 0 || start > addr {
+                continue;
+            }
+            let name = read_u32(symbol, 0)?;
+            if addr < start + size {
+                closest = Some((start, name));
+                break;
+            }
+            if size == 0 && closest.is_none_or(|(closest, _)| start > closest) {
+                closest = Some((start, name));
+            }
+        }
+        let (start, name) = closest?;
+        Some((Symbol(self.name(name)?), addr - start))
+    }
+
+    fn name(&self, offset: u32) -> Option<&'static str> {
+        let name = self.names.get(offset as usize..)?;
+        let len = name.iter().position(|&byte| byte == 0)?;
+        core::str::from_utf8(&name[..len]).ok()
+    }
+}
+
+fn read<const N: usize>(bytes: &[u8], offset: usize) -> Option<[u8; N]> {
+    bytes.get(offset..offset + N)?.try_into().ok()
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(read(bytes, offset)?))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(read(bytes, offset)?))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(read(bytes, offset)?))
+}
+
+#[test_case]
+fn test_walk_finds_frames() {
+    let mut frames = 0;
+    walk(current_frame(), None, |_| frames += 1);
+    assert!(frames > 0);
+}
+
+#[test_case]
+fn test_walk_skips_handler_frames() {
+    // a fake stack of two handler frames, the interrupt stack frame (with or
+    // without an error code) and two frames of the interrupted code
+    for error_code in [0, 1] {
+        let mut stack = [0u64; 16];
+        let base = stack.as_ptr() as u64;
+        let addr = |index: usize| base + index as u64 * 8;
+        let interrupted = 3 + error_code;
+        let caller = interrupted + 5;
+        stack[0] = addr(2);
+        stack[1] = 0x1111;
+        stack[2] = addr(caller);
+        stack[3] = 0x2222;
+        stack[caller] = addr(caller + 2);
+        stack[caller + 1] = 0x3333;
+        stack[caller + 3] = 0x4444;
+
+        let stack = stack.as_ptr();
+        let frame = unsafe { &*(stack.add(interrupted) as *const InterruptStackFrame) };
+        let mut frames = alloc::vec::Vec::new();
+        walk(stack as u64, Some(frame), |address| frames.push(address));
+        assert_eq!(frames, [0x3333, 0x4444]);
+    }
+}
+
+#[cfg(test)]
+#[inline(never)]
+fn resolved() -> u64 {
+    resolved as fn() -> u64 as usize as u64
+}
+
+#[test_case]
+fn test_resolve() {
+    let (symbol, offset) = resolve(resolved() + 1).unwrap();
+    assert_eq!(offset, 1);
+    assert!(alloc::format!("{}", symbol).ends_with("backtrace::resolved"));
+}
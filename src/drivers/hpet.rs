@@ -0,0 +1,110 @@
+use crate::{acpi, memory};
+use conquer_once::spin::OnceCell;
+use x86_64::{
+    PhysAddr, VirtAddr,
+    structures::paging::{FrameAllocator, Mapper, Size4KiB},
+};
+
+const CAPABILITIES: u64 = 0x00;
+const CONFIGURATION: u64 = 0x10;
+const MAIN_COUNTER: u64 = 0xF0;
+const ENABLE: u64 = 1 << 0;
+/// Capabilities bit set if the main counter is 64 bits wide.
+const COUNT_SIZE_CAP: u64 = 1 << 13;
+
+/// Offset of the base address in the ACPI `HPET` table: the SDT header,
+/// the event timer block id and the first four bytes of the generic address.
+const TABLE_BASE_ADDRESS: u64 = 44;
+
+static HPET: OnceCell<Hpet> = OnceCell::uninit();
+
+/// High Precision Event Timer, used as a free-running counter.
+pub struct Hpet {
+    base: VirtAddr,
+    period_fs: u64,
+    /// Mask of the counter bits, a 32-bit counter wraps about every 5 minutes.
+    counter_mask: u64,
+}
+
+impl Hpet {
+    /// Current value of the main counter.
+    pub fn counter(&self) -> u64 {
+        unsafe { self.read(MAIN_COUNTER) }
+    }
+
+    /// Ticks since the counter read `start`, across at most one wrap.
+    pub fn ticks_since(&self, start: u64) -> u64 {
+        self.counter().wrapping_sub(start) & self.counter_mask
+    }
+
+    /// Whether the main counter is 64 bits wide and never wraps in practice.
+    pub fn is_64bit(&self) -> bool {
+        self.counter_mask == u64::MAX
+    }
+
+    /// Length of one counter tick in femtoseconds.
+    pub fn period_fs(&self) -> u64 {
+        self.period_fs
+    }
+
+    /// Counter frequency in Hz.
+    pub fn frequency(&self) -> u64 {
+        1_000_000_000_000_000 / self.period_fs
+    }
+
+    unsafe fn read(&self, register: u64) -> u64 {
+        unsafe { (self.base + register).as_ptr::<u64>().read_volatile() }
+    }
+
+    unsafe fn write(&self, register: u64, value: u64) {
+        unsafe { (self.base + register).as_mut_ptr::<u64>().write_volatile(value) }
+    }
+}
+
+/// Finds the HPET through ACPI, maps its registers and starts the main counter.
+///
+/// Returns `None` if the machine has no HPET.
+pub fn init(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Option<&'static Hpet> {
+    if let Ok(hpet) = HPET.try_get() {
+        return Some(hpet);
+    }
+
+    let table = acpi::find_table(b"HPET")?;
+    let address = unsafe {
+        memory::phys_to_virt(table + TABLE_BASE_ADDRESS)
+            .as_ptr::<u64>()
+            .read_unaligned()
+    };
+    let base = memory::map_mmio(PhysAddr::new(address), 0x400, mapper, frame_allocator).ok()?;
+
+    let mut hpet = Hpet {
+        base,
+        period_fs: 0,
+        counter_mask: 0,
+    };
+    unsafe {
+        let capabilities = hpet.read(CAPABILITIES);
+        hpet.period_fs = capabilities >> 32;
+        hpet.counter_mask = if capabilities & COUNT_SIZE_CAP != 0 {
+            u64::MAX
+        } else {
+            u32::MAX as u64
+        };
+        let config = hpet.read(CONFIGURATION);
+        hpet.write(CONFIGURATION, config | ENABLE);
+    }
+    if hpet.period_fs == 0 {
+        return None;
+    }
+
+    HPET.init_once(|| hpet);
+    HPET.get()
+}
+
+/// The HPET, if [`init`] found one.
+pub fn get() -> Option<&'static Hpet> {
+    HPET.get()
+}
@@ -0,0 +1,327 @@
+//! Input event subsystem.
+//!
+//! Device drivers [`publish`] their events here, and every subscriber gets
+//! its own [`InputStream`] of them, so any number of consumers can listen
+//! at the same time.
+//!
+//! Keyboard input is routed by focus: key events only go to the stream
+//! that has focus, the active console, and to monitors. A stream created
+//! by [`subscribe`] takes focus, and when it is dropped focus returns to
+//! the stream that had it before. Mouse events go to every stream that
+//! listens to the mouse.
+//!
+//! Each device class has its own channel, so a stream of key events only,
+//! see [`subscribe_keys`], cannot fall behind because the mouse is busy.
+//! Events of different devices are not ordered with respect to each other.
+
+use alloc::vec::Vec;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll};
+use futures_util::{ready, stream::Stream};
+use lazy_static::lazy_static;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use super::keyboard::KeyboardEvent;
+use super::mouse::MouseEvent;
+use super::tty::Color;
+use crate::println;
+use crate::task::channel::broadcast::{self, Lagged, TryRecvError};
+
+/// Events a subscriber may fall behind by before it misses some.
+const CAPACITY: usize = 256;
+
+/// An event of any input device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+    Keyboard(KeyboardEvent),
+    Mouse(MouseEvent),
+}
+
+/// Identifies an [`InputStream`] for focus routing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SubscriberId(u64);
+
+/// A key event with the stream it was routed to.
+#[derive(Clone)]
+struct Routed {
+    event: KeyboardEvent,
+    /// The stream that had focus when the event was published, if any.
+    focus: Option<SubscriberId>,
+}
+
+lazy_static! {
+    static ref KEYS: broadcast::Sender<Routed> = broadcast::channel(CAPACITY).0;
+    static ref MOUSE: broadcast::Sender<MouseEvent> = broadcast::channel(CAPACITY).0;
+}
+
+/// Streams that took focus, the focused one last.
+static FOCUS: spin::Mutex<Vec<SubscriberId>> = spin::Mutex::new(Vec::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Hands `event` to the subscribers, called by the device drivers.
+///
+/// Events nobody is subscribed to are dropped.
+pub fn publish(event: InputEvent) {
+    match event {
+        InputEvent::Keyboard(event) => {
+            let _ = KEYS.send(Routed {
+                event,
+                focus: focus(),
+            });
+        }
+        InputEvent::Mouse(event) => {
+            let _ = MOUSE.send(event);
+        }
+    }
+}
+
+/// Creates a stream of the events from now on and gives it focus.
+pub fn subscribe() -> InputStream {
+    focused(InputStream::new(true, true, false))
+}
+
+/// Like [`subscribe`], but the stream gets key events only.
+pub fn subscribe_keys() -> InputStream {
+    focused(InputStream::new(true, false, false))
+}
+
+/// Creates a stream that sees every event from now on, key events
+/// included, and never takes focus.
+pub fn monitor() -> InputStream {
+    InputStream::new(true, true, true)
+}
+
+/// Creates a stream of the mouse events from now on.
+pub fn monitor_mouse() -> InputStream {
+    InputStream::new(false, true, true)
+}
+
+fn focused(stream: InputStream) -> InputStream {
+    without_interrupts(|| FOCUS.lock().push(stream.id));
+    stream
+}
+
+/// The stream key events are routed to, if any.
+pub fn focus() -> Option<SubscriberId> {
+    without_interrupts(|| FOCUS.lock().last().copied())
+}
+
+/// Gives focus to the stream `id`, which must have had focus before.
+///
+/// Returns false if there is no such stream.
+pub fn set_focus(id: SubscriberId) -> bool {
+    without_interrupts(|| {
+        let mut focus = FOCUS.lock();
+        let Some(index) = focus.iter().position(|&other| other == id) else {
+            return false;
+        };
+        focus.remove(index);
+        focus.push(id);
+        true
+    })
+}
+
+/// A subscription to input events, see [`subscribe`] and [`monitor`].
+pub struct InputStream {
+    keys: Option<broadcast::Receiver<Routed>>,
+    mouse: Option<broadcast::Receiver<MouseEvent>>,
+    id: SubscriberId,
+    monitor: bool,
+}
+
+impl InputStream {
+    fn new(keys: bool, mouse: bool, monitor: bool) -> Self {
+        Self {
+            keys: keys.then(|| KEYS.subscribe()),
+            mouse: mouse.then(|| MOUSE.subscribe()),
+            id: SubscriberId(NEXT_ID.fetch_add(1, Ordering::Relaxed)),
+            monitor,
+        }
+    }
+
+    pub fn id(&self) -> SubscriberId {
+        self.id
+    }
+
+    /// Whether key events are routed to this stream.
+    pub fn has_focus(&self) -> bool {
+        focus() == Some(self.id)
+    }
+
+    /// Takes focus back, see [`set_focus`].
+    pub fn focus(&self) -> bool {
+        set_focus(self.id)
+    }
+
+    /// Returns the next event without waiting, if there is one.
+    pub fn try_next(&mut self) -> Option<InputEvent> {
+        let accept = self.key_filter();
+        try_channel(&mut self.keys, accept).or_else(|| try_channel(&mut self.mouse, mouse_event))
+    }
+
+    /// Maps the key events routed to this stream to input events.
+    fn key_filter(&self) -> impl Fn(Routed) -> Option<InputEvent> + use<> {
+        let (id, monitor) = (self.id, self.monitor);
+        move |routed| {
+            (monitor || routed.focus == Some(id)).then_some(InputEvent::Keyboard(routed.event))
+        }
+    }
+}
+
+impl Stream for InputStream {
+    type Item = InputEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let accept = self.key_filter();
+        let keys = poll_channel(&mut self.keys, cx, accept);
+        if let Poll::Ready(Some(event)) = keys {
+            return Poll::Ready(Some(event));
+        }
+        match (keys, poll_channel(&mut self.mouse, cx, mouse_event)) {
+            (_, Poll::Ready(Some(event))) => Poll::Ready(Some(event)),
+            (Poll::Ready(None), Poll::Ready(None)) => Poll::Ready(None),
+            _ => Poll::Pending,
+        }
+    }
+}
+
+fn mouse_event(event: MouseEvent) -> Option<InputEvent> {
+    Some(InputEvent::Mouse(event))
+}
+
+/// Takes values from `receiver` until `map` turns one into an event.
+/// Returns `None` once the channel is empty, or if there is no receiver.
+fn try_channel<T: Clone>(
+    receiver: &mut Option<broadcast::Receiver<T>>,
+    map: impl Fn(T) -> Option<InputEvent>,
+) -> Option<InputEvent> {
+    let receiver = receiver.as_mut()?;
+    loop {
+        match receiver.try_recv() {
+            Ok(value) => {
+                if let Some(event) = map(value) {
+                    return Some(event);
+                }
+            }
+            Err(TryRecvError::Lagged(missed)) => lagged(missed),
+            Err(TryRecvError::Empty | TryRecvError::Closed) => return None,
+        }
+    }
+}
+
+/// Like [`try_channel`], but waits for an event. Ready with `None` once
+/// the channel is closed, or if there is no receiver.
+fn poll_channel<T: Clone>(
+    receiver: &mut Option<broadcast::Receiver<T>>,
+    cx: &mut Context<'_>,
+    map: impl Fn(T) -> Option<InputEvent>,
+) -> Poll<Option<InputEvent>> {
+    let Some(receiver) = receiver else {
+        return Poll::Ready(None);
+    };
+    loop {
+        match ready!(Pin::new(&mut *receiver).poll_next(cx)) {
+            Some(Ok(value)) => {
+                if let Some(event) = map(value) {
+                    return Poll::Ready(Some(event));
+                }
+            }
+            Some(Err(Lagged(missed))) => lagged(missed),
+            None => return Poll::Ready(None),
+        }
+    }
+}
+
+impl Drop for InputStream {
+    fn drop(&mut self) {
+        if !self.monitor {
+            without_interrupts(|| FOCUS.lock().retain(|&id| id != self.id));
+        }
+    }
+}
+
+fn lagged(missed: u64) {
+    println!(
+        (Color::Yellow, Color::Black),
+        "WARNING: input subscriber lagged, {} events lost", missed
+    );
+}
+
+#[cfg(test)]
+fn key_event(code: super::keyboard::KeyCode) -> InputEvent {
+    use super::keyboard::{Key, Modifiers};
+
+    InputEvent::Keyboard(KeyboardEvent::KeyPress(Key {
+        code,
+        decoded: None,
+        modifiers: Modifiers::default(),
+    }))
+}
+
+#[test_case]
+fn test_every_subscriber_gets_mouse_events() {
+    let mut first = subscribe();
+    let mut second = monitor();
+    let event = InputEvent::Mouse(MouseEvent::Scroll(1));
+    publish(event);
+    assert_eq!(first.try_next(), Some(event));
+    assert_eq!(second.try_next(), Some(event));
+    assert_eq!(first.try_next(), None);
+}
+
+#[test_case]
+fn test_key_events_follow_focus() {
+    use super::keyboard::KeyCode;
+
+    let mut monitor = monitor();
+    let mut console = subscribe();
+    let mut other = subscribe();
+    assert!(other.has_focus());
+
+    publish(key_event(KeyCode::A));
+    assert_eq!(other.try_next(), Some(key_event(KeyCode::A)));
+    assert_eq!(console.try_next(), None);
+
+    assert!(console.focus());
+    publish(key_event(KeyCode::B));
+    assert_eq!(console.try_next(), Some(key_event(KeyCode::B)));
+    assert_eq!(other.try_next(), None);
+
+    // focus returns to the previous stream
+    assert!(other.focus());
+    drop(other);
+    assert!(console.has_focus());
+    publish(key_event(KeyCode::C));
+    assert_eq!(console.try_next(), Some(key_event(KeyCode::C)));
+
+    // the monitor saw every key
+    for code in [KeyCode::A, KeyCode::B, KeyCode::C] {
+        assert_eq!(monitor.try_next(), Some(key_event(code)));
+    }
+    assert!(!monitor.focus());
+}
+
+#[test_case]
+fn test_mouse_does_not_lag_key_streams() {
+    use super::keyboard::KeyCode;
+
+    let mut keys = subscribe_keys();
+    let mut mouse = monitor_mouse();
+    publish(key_event(KeyCode::A));
+    for _ in 0..2 * CAPACITY {
+        publish(InputEvent::Mouse(MouseEvent::Scroll(1)));
+    }
+    assert_eq!(keys.try_next(), Some(key_event(KeyCode::A)));
+    assert_eq!(keys.try_next(), None);
+    // the mouse stream lagged behind and gets the latest events
+    assert_eq!(
+        mouse.try_next(),
+        Some(InputEvent::Mouse(MouseEvent::Scroll(1)))
+    );
+    // and it never sees keys
+    publish(key_event(KeyCode::B));
+    assert!(
+        core::iter::from_fn(|| mouse.try_next()).all(|event| matches!(event, InputEvent::Mouse(_)))
+    );
+}
@@ -1,85 +1,299 @@
-use core::sync::atomic::{AtomicBool, Ordering};
-use crossbeam_queue::ArrayQueue;
-use futures_util::task::AtomicWaker;
-use pc_keyboard::{Keyboard, ScancodeSet1, layouts, HandleControl, DecodedKey};
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use core::time::Duration;
+use pc_keyboard::{Keyboard, KeyState, ScancodeSet1, HandleControl};
+pub use pc_keyboard::{DecodedKey, KeyCode};
+
+mod layout;
+
+use super::input::{self, InputEvent, InputStream};
+use super::ps2;
+
+pub use layout::Layout;
+use layout::ActiveLayout;
 
-use alloc::sync::Arc;
 use spin::Mutex;
 
 /// Driver event
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum KeyboardEvent {
-    KeyPress(DecodedKey),
-    KeyRelease(DecodedKey),
+    KeyPress(Key),
+    KeyRelease(Key),
+}
+
+impl KeyboardEvent {
+    pub fn key(&self) -> &Key {
+        match self {
+            KeyboardEvent::KeyPress(key) | KeyboardEvent::KeyRelease(key) => key,
+        }
+    }
+}
+
+/// A key that went down or up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+    pub code: KeyCode,
+    /// The key as decoded by the layout. A release carries what the press
+    /// decoded to, or `None` if the press was not seen.
+    pub decoded: Option<DecodedKey>,
+    /// Modifiers after the event, so pressing Shift reports `shift` set.
+    pub modifiers: Modifiers,
+}
+
+/// Snapshot of the modifier keys and lock toggles.
+///
+/// Left and right keys are merged; [`Key::code`] tells them apart.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    /// Either Windows key.
+    pub meta: bool,
+    pub caps_lock: bool,
+    pub num_lock: bool,
+    pub scroll_lock: bool,
+}
+
+/// Key combination that switches between the two configured layouts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hotkey {
+    AltShift,
+    CtrlShift,
+}
+
+/// Turns scancodes into [`KeyboardEvent`]s.
+///
+/// Keys are decoded with one of two configured layouts, US and Russian by
+/// default; Alt+Shift switches between them.
+pub struct Decoder {
+    keyboard: Keyboard<ActiveLayout, ScancodeSet1>,
+    layout: ActiveLayout,
+    /// The layouts the hotkey switches between.
+    layouts: [Layout; 2],
+    /// Index of the active layout in `layouts`.
+    active: usize,
+    hotkey: Option<Hotkey>,
+    lwin: bool,
+    rwin: bool,
+    scroll_lock: bool,
+    /// What each held key decoded to, indexed by key code.
+    pressed: [Option<DecodedKey>; 256],
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        let layout = ActiveLayout::new(Layout::Us);
+        Self {
+            keyboard: Keyboard::new(ScancodeSet1::new(), layout.clone(), HandleControl::Ignore),
+            layout,
+            layouts: [Layout::Us, Layout::Russian],
+            active: 0,
+            hotkey: Some(Hotkey::AltShift),
+            lwin: false,
+            rwin: false,
+            scroll_lock: false,
+            pressed: [None; 256],
+        }
+    }
+
+    /// The layout keys are currently decoded with.
+    pub fn layout(&self) -> Layout {
+        self.layout.get()
+    }
+
+    /// The two layouts the hotkey switches between.
+    pub fn layouts(&self) -> [Layout; 2] {
+        self.layouts
+    }
+
+    /// Replaces the active layout.
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.layouts[self.active] = layout;
+        self.layout.set(layout);
+    }
+
+    /// Configures the layouts the hotkey switches between and activates
+    /// `primary`.
+    pub fn set_layouts(&mut self, primary: Layout, secondary: Layout) {
+        self.layouts = [primary, secondary];
+        self.active = 0;
+        self.layout.set(primary);
+    }
+
+    /// Switches to the other configured layout and returns it.
+    pub fn toggle_layout(&mut self) -> Layout {
+        self.active ^= 1;
+        self.layout.set(self.layouts[self.active]);
+        self.layouts[self.active]
+    }
+
+    /// Sets the layout switching hotkey, `None` disables it.
+    pub fn set_hotkey(&mut self, hotkey: Option<Hotkey>) {
+        self.hotkey = hotkey;
+    }
+
+    /// Whether pressing `code` completes the layout switching hotkey.
+    fn is_hotkey(&self, code: KeyCode, modifiers: Modifiers) -> bool {
+        let shift = matches!(code, KeyCode::LShift | KeyCode::RShift);
+        match self.hotkey {
+            Some(Hotkey::AltShift) => {
+                (shift && modifiers.alt)
+                    || (matches!(code, KeyCode::LAlt | KeyCode::RAltGr) && modifiers.shift)
+            }
+            Some(Hotkey::CtrlShift) => {
+                (shift && modifiers.ctrl)
+                    || (matches!(code, KeyCode::LControl | KeyCode::RControl) && modifiers.shift)
+            }
+            None => false,
+        }
+    }
+
+    pub fn modifiers(&self) -> Modifiers {
+        let state = self.keyboard.get_modifiers();
+        Modifiers {
+            shift: state.is_shifted(),
+            ctrl: state.is_ctrl(),
+            alt: state.lalt || state.ralt,
+            meta: self.lwin || self.rwin,
+            caps_lock: state.capslock,
+            num_lock: state.numlock,
+            scroll_lock: self.scroll_lock,
+        }
+    }
+
+    /// Feeds one scancode byte, returns an event once a key sequence is
+    /// complete.
+    pub fn add_byte(&mut self, scancode: u8) -> Option<KeyboardEvent> {
+        let event = self.keyboard.add_byte(scancode).ok()??;
+        let down = event.state != KeyState::Up;
+        let code = event.code;
+        // the hidden keys still go through the keyboard state, which tells
+        // Pause apart from Num Lock by the hidden Ctrl pressed before it
+        let decoded = self.keyboard.process_keyevent(event);
+        let pause_held = self.pressed[KeyCode::PauseBreak as usize].is_some();
+        let code = match code {
+            // not keys: status bytes and the hidden halves of Pause and
+            // Print Screen
+            KeyCode::PowerOnTestOk | KeyCode::TooManyKeys | KeyCode::RControl2 | KeyCode::RAlt2 => {
+                return None;
+            }
+            KeyCode::NumpadLock if decoded == Some(DecodedKey::RawKey(KeyCode::PauseBreak)) => {
+                KeyCode::PauseBreak
+            }
+            // Pause releases its hidden Ctrl first
+            KeyCode::NumpadLock if !down && pause_held => KeyCode::PauseBreak,
+            code => code,
+        };
+        match code {
+            KeyCode::LWin => self.lwin = down,
+            KeyCode::RWin => self.rwin = down,
+            KeyCode::ScrollLock if down => self.scroll_lock = !self.scroll_lock,
+            _ => {}
+        }
+        let slot = &mut self.pressed[code as usize];
+        // typematic repeats are presses of a key that is already down
+        let repeat = down && slot.is_some();
+        let decoded = if down {
+            *slot = decoded;
+            decoded
+        } else {
+            slot.take()
+        };
+        let key = Key { code, decoded, modifiers: self.modifiers() };
+        if down && !repeat && self.is_hotkey(code, key.modifiers) {
+            self.toggle_layout();
+        }
+        Some(if down {
+            KeyboardEvent::KeyPress(key)
+        } else {
+            KeyboardEvent::KeyRelease(key)
+        })
+    }
 }
 
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// keyboard commands
+const SET_LEDS: u8 = 0xED;
+const SET_TYPEMATIC: u8 = 0xF3;
+const ENABLE_SCANNING: u8 = 0xF4;
+const RESET: u8 = 0xFF;
+const RESET_PASSED: u8 = 0xAA;
+
+const LED_SCROLL_LOCK: u8 = 1 << 0;
+const LED_NUM_LOCK: u8 = 1 << 1;
+const LED_CAPS_LOCK: u8 = 1 << 2;
+
+/// Typematic delay and rate set by [`KeyboardDriver::init`].
+pub const DEFAULT_TYPEMATIC_DELAY: Duration = Duration::from_millis(500);
+pub const DEFAULT_TYPEMATIC_RATE: u32 = 10;
+
 /// Global driver state
 pub struct KeyboardDriver {
-    keyboard: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>>,
-    queue: Arc<ArrayQueue<KeyboardEvent>>,
-    waker: AtomicWaker,
+    decoder: Mutex<Decoder>,
     initialized: AtomicBool,
+    /// LED byte last sent to the keyboard.
+    leds: AtomicU8,
 }
 
 impl KeyboardDriver {
     pub fn new() -> Self {
         Self {
-            keyboard: Mutex::new(Keyboard::new(
-                ScancodeSet1::new(),
-                layouts::Us104Key,
-                HandleControl::Ignore,
-            )),
-            queue: Arc::new(ArrayQueue::new(256)),
-            waker: AtomicWaker::new(),
+            decoder: Mutex::new(Decoder::new()),
             initialized: AtomicBool::new(false),
+            leds: AtomicU8::new(0),
         }
     }
 
-    /// PS/2 init
-    pub fn init(&self) {
+    /// Sets up the PS/2 controller, resets the keyboard, sets the typematic
+    /// rate and the LEDs and enables scanning.
+    pub fn init(&self) -> Result<(), ps2::Error> {
         if self.initialized.swap(true, Ordering::SeqCst) {
-            return;
+            return Ok(());
         }
 
-        unsafe {
-            use x86_64::instructions::port::Port;
-            let mut cmd = Port::<u8>::new(0x64);
-            let mut data = Port::<u8>::new(0x60);
-
-            // Cleaning buffer
-            while (cmd.read() & 1) != 0 {
-                let _: u8 = data.read();
-            }
-
-            // Enabling keyboard interruption
-            cmd.write(0xAEu8); // enable first PS/2 port
-            data.write(0xF4u8); // enable scanning
+        ps2::init()?;
+        let mut result = [0];
+        ps2::query(ps2::Port::First, &[RESET], &mut result, ps2::RESET_TIMEOUT)?;
+        if result[0] != RESET_PASSED {
+            return Err(ps2::Error::Unexpected(result[0]));
         }
+        set_typematic(DEFAULT_TYPEMATIC_DELAY, DEFAULT_TYPEMATIC_RATE)?;
+        self.update_leds(self.modifiers(), true)?;
+        ps2::command(ps2::Port::First, &[ENABLE_SCANNING])
     }
 
-    /// Scancode IRQ-handler
+    /// Decodes a scancode, called from the keyboard bottom half
     pub fn handle_scancode(&self, scancode: u8) {
-        let mut kb = self.keyboard.lock();
-        if let Ok(Some(event)) = kb.add_byte(scancode) {
-            if let Some(decoded) = kb.process_keyevent(event) {
-                let ev = KeyboardEvent::KeyPress(decoded); // TODO: KeyRelease
-                if let Err(_) = self.queue.push(ev) {
-                    // Queue overflow
-                    println!((Color::Yellow, Color::Black), "WARNING: Keyboard queue overflow");
-                } else {
-                    self.waker.wake();
-                }
+        let event = self.decoder.lock().add_byte(scancode);
+        if let Some(ev) = event {
+            if let Err(err) = self.update_leds(ev.key().modifiers, false) {
+                println!((Color::Yellow, Color::Black), "WARNING: cannot set keyboard LEDs: {:?}", err);
             }
+            input::publish(InputEvent::Keyboard(ev));
         }
     }
-    
-    /// Get event queue (for async Stream)
-    pub fn queue(&self) -> Arc<ArrayQueue<KeyboardEvent>> {
-        self.queue.clone()
+
+    /// Lights the lock LEDs according to `modifiers`, unless they already
+    /// are and `force` is false.
+    fn update_leds(&self, modifiers: Modifiers, force: bool) -> Result<(), ps2::Error> {
+        let leds = led_byte(modifiers);
+        if self.leds.load(Ordering::Relaxed) == leds && !force {
+            return Ok(());
+        }
+        ps2::command(ps2::Port::First, &[SET_LEDS, leds])?;
+        // only once the keyboard has them, so a failed update is retried
+        self.leds.store(leds, Ordering::Relaxed);
+        Ok(())
     }
-    
-    pub fn register_waker(&self, waker: &core::task::Waker) {
-        self.waker.register(waker);
+
+    /// Current modifier state.
+    pub fn modifiers(&self) -> Modifiers {
+        self.decoder.lock().modifiers()
     }
 }
 
@@ -89,50 +303,132 @@ static DRIVER: OnceCell<KeyboardDriver> = OnceCell::uninit();
 
 pub fn init_keyboard() {
     let driver = KeyboardDriver::new();
-    driver.init();
+    if let Err(err) = driver.init() {
+        println!((Color::Yellow, Color::Black), "WARNING: keyboard init failed: {:?}", err);
+    }
     DRIVER.init_once(move || driver);
 }
 
+/// Sets how long a key must be held before it repeats, and how many times
+/// per second it then repeats.
+///
+/// Rounded to what the keyboard supports: a delay of 250 to 1000 ms in
+/// steps of 250 ms and 2 to 30 repeats per second.
+pub fn set_typematic(delay: Duration, rate: u32) -> Result<(), ps2::Error> {
+    ps2::command(ps2::Port::First, &[SET_TYPEMATIC, typematic_byte(delay, rate)])
+}
+
+/// Encodes the argument of the set typematic command: the delay in bits
+/// 5-6, the repeat period in bits 0-4.
+fn typematic_byte(delay: Duration, rate: u32) -> u8 {
+    let delay = ((delay.as_millis() as u64 + 125) / 250).clamp(1, 4) as u8 - 1;
+    // the period is (8 + A) * 2^B * 4.17 ms with A in bits 0-2, B in bits 3-4
+    let period_us = |code: u8| (8 + u32::from(code & 7)) * (1 << (code >> 3)) * 4170;
+    let wanted_us = 1_000_000 / rate.clamp(2, 30);
+    let rate = (0..32)
+        .min_by_key(|&code| period_us(code).abs_diff(wanted_us))
+        .unwrap_or(0);
+    delay << 5 | rate
+}
+
+fn led_byte(modifiers: Modifiers) -> u8 {
+    let mut leds = 0;
+    if modifiers.scroll_lock {
+        leds |= LED_SCROLL_LOCK;
+    }
+    if modifiers.num_lock {
+        leds |= LED_NUM_LOCK;
+    }
+    if modifiers.caps_lock {
+        leds |= LED_CAPS_LOCK;
+    }
+    leds
+}
+
+fn decoder() -> spin::MutexGuard<'static, Decoder> {
+    DRIVER.try_get().expect("keyboard not initialized").decoder.lock()
+}
+
+/// The layout keys are currently decoded with.
+pub fn layout() -> Layout {
+    decoder().layout()
+}
+
+/// The two layouts the layout hotkey switches between.
+pub fn layouts() -> [Layout; 2] {
+    decoder().layouts()
+}
+
+/// Replaces the active layout, see [`Decoder::set_layout`].
+pub fn set_layout(layout: Layout) {
+    decoder().set_layout(layout);
+}
+
+/// Configures the layouts the hotkey switches between and activates
+/// `primary`.
+pub fn set_layouts(primary: Layout, secondary: Layout) {
+    decoder().set_layouts(primary, secondary);
+}
+
+/// Switches to the other configured layout and returns it.
+pub fn toggle_layout() -> Layout {
+    decoder().toggle_layout()
+}
+
+/// Sets the layout switching hotkey, `None` disables it.
+pub fn set_layout_hotkey(hotkey: Option<Hotkey>) {
+    decoder().set_hotkey(hotkey);
+}
+
+/// Queues a scancode for decoding in the bottom half.
 pub fn add_scancode_from_irq(scancode: u8) {
+    let _ = deferred::schedule(Work::new(process_scancode, scancode as u64));
+}
+
+/// Bottom half of the keyboard IRQ, runs with interrupts enabled.
+fn process_scancode(scancode: u64) {
     if let Ok(driver) = DRIVER.try_get() {
-        driver.handle_scancode(scancode);
+        driver.handle_scancode(scancode as u8);
     }
 }
 
 /// Async keyboard events stream
 use core::pin::Pin;
 use core::task::{Context, Poll};
-use futures_util::stream::Stream;
+use futures_util::{ready, stream::Stream};
 
 use crate::drivers::tty::Color;
+use crate::interrupts::deferred::{self, Work};
 use crate::{println};
 
+/// Key events of an input subscription, see [`input::subscribe_keys`].
 pub struct KeyboardStream {
-    queue: Arc<ArrayQueue<KeyboardEvent>>,
+    input: InputStream,
 }
 
 impl KeyboardStream {
+    /// Subscribes to input and takes focus, so key events go to the new
+    /// stream until it is dropped or another stream takes focus.
     pub fn new() -> Self {
-        let driver = DRIVER.try_get().expect("keyboard not initialized");
-        Self { queue: driver.queue() }
+        Self { input: input::subscribe_keys() }
+    }
+
+    /// The underlying subscription, for focus control.
+    pub fn input(&self) -> &InputStream {
+        &self.input
     }
 }
 
 impl Stream for KeyboardStream {
     type Item = KeyboardEvent;
-    
-    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        if let Some(event) = self.queue.pop() {
-            return Poll::Ready(Some(event));
-        }
-        
-        if let Ok(driver) = DRIVER.try_get() {
-            driver.register_waker(cx.waker());
-        }
-        
-        match self.queue.pop() {
-            Some(ev) => Poll::Ready(Some(ev)),
-            None => Poll::Pending,
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match ready!(Pin::new(&mut self.input).poll_next(cx)) {
+                Some(InputEvent::Keyboard(event)) => return Poll::Ready(Some(event)),
+                Some(_) => {}
+                None => return Poll::Ready(None),
+            }
         }
     }
 }
@@ -142,14 +438,139 @@ use crate::interrupts::PICS;
 use crate::interrupts::InterruptIndex;
 
 pub extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    use x86_64::instructions::port::Port;
-
-    let mut port = Port::new(0x60);
-    let scancode: u8 = unsafe { port.read() };
-    crate::keyboard::add_scancode_from_irq(scancode);
+    let _irq = crate::percpu::enter_interrupt();
+    crate::interrupts::stats::record(InterruptIndex::Keyboard.as_u8());
+    if let Some(scancode) = ps2::read_irq_byte(ps2::Port::First) {
+        crate::keyboard::add_scancode_from_irq(scancode);
+    }
 
     unsafe {
         PICS.lock()
         .notify_end_of_interrupt(InterruptIndex::Keyboard as u8);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+fn feed(decoder: &mut Decoder, scancodes: &[u8]) -> Option<KeyboardEvent> {
+    scancodes.iter().fold(None, |_, &scancode| decoder.add_byte(scancode))
+}
+
+#[test_case]
+fn test_press_and_release() {
+    let mut decoder = Decoder::new();
+    let press = feed(&mut decoder, &[0x1E]).unwrap();
+    assert_eq!(press.key().code, KeyCode::A);
+    assert_eq!(press, KeyboardEvent::KeyPress(*press.key()));
+    assert_eq!(press.key().decoded, Some(DecodedKey::Unicode('a')));
+    let release = feed(&mut decoder, &[0x9E]).unwrap();
+    assert_eq!(release, KeyboardEvent::KeyRelease(*press.key()));
+}
+
+#[test_case]
+fn test_modifier_snapshot() {
+    let mut decoder = Decoder::new();
+    let shift = feed(&mut decoder, &[0x2A]).unwrap();
+    assert!(shift.key().modifiers.shift);
+    let press = feed(&mut decoder, &[0x1E]).unwrap();
+    assert_eq!(press.key().decoded, Some(DecodedKey::Unicode('A')));
+    // the release decodes like the press, even with Shift already up
+    feed(&mut decoder, &[0xAA]).unwrap();
+    let release = feed(&mut decoder, &[0x9E]).unwrap();
+    assert_eq!(release.key().decoded, Some(DecodedKey::Unicode('A')));
+    assert!(!release.key().modifiers.shift);
+}
+
+#[test_case]
+fn test_ctrl_alt_meta() {
+    let mut decoder = Decoder::new();
+    feed(&mut decoder, &[0x1D]); // left Ctrl
+    feed(&mut decoder, &[0x38]); // left Alt
+    feed(&mut decoder, &[0xE0, 0x5B]); // left Windows
+    let c = feed(&mut decoder, &[0x2E]).unwrap();
+    let modifiers = c.key().modifiers;
+    assert!(modifiers.ctrl && modifiers.alt && modifiers.meta);
+    feed(&mut decoder, &[0xE0, 0xDB]);
+    feed(&mut decoder, &[0x9D]);
+    assert!(!decoder.modifiers().meta && !decoder.modifiers().ctrl);
+}
+
+#[test_case]
+fn test_lock_toggles() {
+    let mut decoder = Decoder::new();
+    let initial = decoder.modifiers();
+    feed(&mut decoder, &[0x3A, 0xBA]); // Caps Lock
+    feed(&mut decoder, &[0x46, 0xC6]); // Scroll Lock
+    feed(&mut decoder, &[0x45, 0xC5]); // Num Lock
+    let modifiers = decoder.modifiers();
+    assert!(modifiers.caps_lock && modifiers.scroll_lock);
+    assert_ne!(modifiers.num_lock, initial.num_lock);
+}
+
+#[test_case]
+fn test_pause() {
+    let mut decoder = Decoder::new();
+    let num_lock = decoder.modifiers().num_lock;
+    let press = feed(&mut decoder, &[0xE1, 0x1D, 0x45]).unwrap();
+    assert_eq!(press.key().code, KeyCode::PauseBreak);
+    assert_eq!(press, KeyboardEvent::KeyPress(*press.key()));
+    assert_eq!(press.key().decoded, Some(DecodedKey::RawKey(KeyCode::PauseBreak)));
+    let release = feed(&mut decoder, &[0xE1, 0x9D, 0xC5]).unwrap();
+    assert_eq!(release, KeyboardEvent::KeyRelease(*press.key()));
+    assert_eq!(decoder.modifiers().num_lock, num_lock);
+}
+
+#[test_case]
+fn test_layout_hotkey() {
+    let mut decoder = Decoder::new();
+    assert_eq!(decoder.layout(), Layout::Us);
+    feed(&mut decoder, &[0x38]); // left Alt
+    feed(&mut decoder, &[0x2A]); // left Shift
+    assert_eq!(decoder.layout(), Layout::Russian);
+    // holding the keys repeats the press but switches only once
+    feed(&mut decoder, &[0x2A]);
+    assert_eq!(decoder.layout(), Layout::Russian);
+    feed(&mut decoder, &[0xAA, 0xB8]);
+    let q = feed(&mut decoder, &[0x10]).unwrap();
+    assert_eq!(q.key().decoded, Some(DecodedKey::Unicode('й')));
+    assert_eq!(decoder.toggle_layout(), Layout::Us);
+}
+
+#[test_case]
+fn test_set_layouts() {
+    let mut decoder = Decoder::new();
+    decoder.set_layouts(Layout::De, Layout::Fr);
+    let y = feed(&mut decoder, &[0x15]).unwrap();
+    assert_eq!(y.key().decoded, Some(DecodedKey::Unicode('z')));
+    decoder.toggle_layout();
+    decoder.set_layout(Layout::Dvorak);
+    assert_eq!(decoder.layouts(), [Layout::De, Layout::Dvorak]);
+    decoder.set_hotkey(None);
+    feed(&mut decoder, &[0x38, 0x2A]);
+    assert_eq!(decoder.layout(), Layout::Dvorak);
+}
+
+#[test_case]
+fn test_typematic_byte() {
+    assert_eq!(typematic_byte(Duration::from_millis(250), 30), 0x00);
+    assert_eq!(typematic_byte(Duration::from_millis(1000), 2), 0x7F);
+    assert_eq!(typematic_byte(Duration::from_millis(500), 10), 0x2C);
+    // out of range values are clamped
+    assert_eq!(typematic_byte(Duration::ZERO, 100), 0x00);
+}
+
+#[test_case]
+fn test_led_byte() {
+    let modifiers = Modifiers {
+        caps_lock: true,
+        scroll_lock: true,
+        ..Modifiers::default()
+    };
+    assert_eq!(led_byte(modifiers), LED_CAPS_LOCK | LED_SCROLL_LOCK);
+}
+
+#[test_case]
+fn test_keyboard_accepts_commands() {
+    set_typematic(DEFAULT_TYPEMATIC_DELAY, DEFAULT_TYPEMATIC_RATE).unwrap();
+    let leds = led_byte(decoder().modifiers());
+    ps2::command(ps2::Port::First, &[SET_LEDS, leds]).unwrap();
+}
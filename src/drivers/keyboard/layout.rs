@@ -0,0 +1,203 @@
+//! Keyboard layouts selectable at runtime.
+
+use alloc::sync::Arc;
+use core::fmt;
+use core::sync::atomic::{AtomicU8, Ordering};
+use pc_keyboard::{DecodedKey, HandleControl, KeyCode, KeyboardLayout, Modifiers, layouts};
+
+/// A keyboard layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Layout {
+    /// US 104-key.
+    Us,
+    /// UK 105-key.
+    Uk,
+    /// German 105-key (QWERTZ).
+    De,
+    /// French AZERTY.
+    Fr,
+    /// US Dvorak.
+    Dvorak,
+    /// Russian ЙЦУКЕН on a US keyboard.
+    Russian,
+}
+
+impl Layout {
+    pub const ALL: [Layout; 6] = [
+        Layout::Us,
+        Layout::Uk,
+        Layout::De,
+        Layout::Fr,
+        Layout::Dvorak,
+        Layout::Russian,
+    ];
+
+    /// Short name, as used by [`Layout::from_name`].
+    pub fn name(self) -> &'static str {
+        match self {
+            Layout::Us => "us",
+            Layout::Uk => "uk",
+            Layout::De => "de",
+            Layout::Fr => "fr",
+            Layout::Dvorak => "dvorak",
+            Layout::Russian => "ru",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Layout> {
+        Layout::ALL.into_iter().find(|layout| layout.name() == name)
+    }
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl KeyboardLayout for Layout {
+    fn map_keycode(
+        &self,
+        keycode: KeyCode,
+        modifiers: &Modifiers,
+        handle_ctrl: HandleControl,
+    ) -> DecodedKey {
+        match self {
+            Layout::Us => layouts::Us104Key.map_keycode(keycode, modifiers, handle_ctrl),
+            Layout::Uk => layouts::Uk105Key.map_keycode(keycode, modifiers, handle_ctrl),
+            Layout::De => layouts::De105Key.map_keycode(keycode, modifiers, handle_ctrl),
+            Layout::Fr => layouts::Azerty.map_keycode(keycode, modifiers, handle_ctrl),
+            Layout::Dvorak => layouts::Dvorak104Key.map_keycode(keycode, modifiers, handle_ctrl),
+            Layout::Russian => map_russian(keycode, modifiers, handle_ctrl),
+        }
+    }
+}
+
+/// Letters of the ЙЦУКЕН layout by the US key they sit on.
+const RUSSIAN_LETTERS: [(KeyCode, char); 33] = [
+    (KeyCode::Oem8, 'ё'),
+    (KeyCode::Q, 'й'),
+    (KeyCode::W, 'ц'),
+    (KeyCode::E, 'у'),
+    (KeyCode::R, 'к'),
+    (KeyCode::T, 'е'),
+    (KeyCode::Y, 'н'),
+    (KeyCode::U, 'г'),
+    (KeyCode::I, 'ш'),
+    (KeyCode::O, 'щ'),
+    (KeyCode::P, 'з'),
+    (KeyCode::Oem4, 'х'),
+    (KeyCode::Oem6, 'ъ'),
+    (KeyCode::A, 'ф'),
+    (KeyCode::S, 'ы'),
+    (KeyCode::D, 'в'),
+    (KeyCode::F, 'а'),
+    (KeyCode::G, 'п'),
+    (KeyCode::H, 'р'),
+    (KeyCode::J, 'о'),
+    (KeyCode::K, 'л'),
+    (KeyCode::L, 'д'),
+    (KeyCode::Oem1, 'ж'),
+    (KeyCode::Oem3, 'э'),
+    (KeyCode::Z, 'я'),
+    (KeyCode::X, 'ч'),
+    (KeyCode::C, 'с'),
+    (KeyCode::V, 'м'),
+    (KeyCode::B, 'и'),
+    (KeyCode::N, 'т'),
+    (KeyCode::M, 'ь'),
+    (KeyCode::OemComma, 'б'),
+    (KeyCode::OemPeriod, 'ю'),
+];
+
+/// Russian ЙЦУКЕН: Cyrillic letters and the punctuation that moved, every
+/// other key as on the US layout.
+fn map_russian(keycode: KeyCode, modifiers: &Modifiers, handle_ctrl: HandleControl) -> DecodedKey {
+    let us = || layouts::Us104Key.map_keycode(keycode, modifiers, handle_ctrl);
+    if modifiers.is_ctrl() && handle_ctrl == HandleControl::MapLettersToUnicode {
+        // control characters are only defined for Latin letters
+        return us();
+    }
+    if let Some(&(_, letter)) = RUSSIAN_LETTERS.iter().find(|(code, _)| *code == keycode) {
+        let letter = if modifiers.is_caps() {
+            letter.to_uppercase().next().unwrap_or(letter)
+        } else {
+            letter
+        };
+        return DecodedKey::Unicode(letter);
+    }
+    let shifted = match keycode {
+        KeyCode::Key2 => '"',
+        KeyCode::Key3 => '№',
+        KeyCode::Key4 => ';',
+        KeyCode::Key6 => ':',
+        KeyCode::Key7 => '?',
+        KeyCode::Oem2 => ',',
+        KeyCode::Oem7 => '/',
+        _ => return us(),
+    };
+    match keycode {
+        _ if modifiers.is_shifted() => DecodedKey::Unicode(shifted),
+        KeyCode::Oem2 => DecodedKey::Unicode('.'),
+        KeyCode::Oem7 => DecodedKey::Unicode('\\'),
+        _ => us(),
+    }
+}
+
+/// The layout a [`Decoder`](super::Decoder) maps keys with.
+///
+/// `pc_keyboard::Keyboard` owns its layout and cannot swap it, so it holds a
+/// handle to a layout shared with the decoder instead.
+#[derive(Clone)]
+pub(super) struct ActiveLayout(Arc<AtomicU8>);
+
+impl ActiveLayout {
+    pub(super) fn new(layout: Layout) -> Self {
+        ActiveLayout(Arc::new(AtomicU8::new(layout as u8)))
+    }
+
+    pub(super) fn get(&self) -> Layout {
+        Layout::ALL[usize::from(self.0.load(Ordering::Relaxed))]
+    }
+
+    pub(super) fn set(&self, layout: Layout) {
+        self.0.store(layout as u8, Ordering::Relaxed);
+    }
+}
+
+impl KeyboardLayout for ActiveLayout {
+    fn map_keycode(
+        &self,
+        keycode: KeyCode,
+        modifiers: &Modifiers,
+        handle_ctrl: HandleControl,
+    ) -> DecodedKey {
+        self.get().map_keycode(keycode, modifiers, handle_ctrl)
+    }
+}
+
+#[test_case]
+fn test_layout_names() {
+    for layout in Layout::ALL {
+        assert_eq!(Layout::from_name(layout.name()), Some(layout));
+    }
+    assert_eq!(Layout::from_name("xx"), None);
+}
+
+#[test_case]
+fn test_russian() {
+    let mut modifiers = Modifiers::default();
+    let map = |code, modifiers: &Modifiers| {
+        Layout::Russian.map_keycode(code, modifiers, HandleControl::Ignore)
+    };
+    assert_eq!(map(KeyCode::Q, &modifiers), DecodedKey::Unicode('й'));
+    assert_eq!(map(KeyCode::Oem2, &modifiers), DecodedKey::Unicode('.'));
+    assert_eq!(map(KeyCode::Key1, &modifiers), DecodedKey::Unicode('1'));
+    modifiers.lshift = true;
+    assert_eq!(map(KeyCode::Oem8, &modifiers), DecodedKey::Unicode('Ё'));
+    assert_eq!(map(KeyCode::Key3, &modifiers), DecodedKey::Unicode('№'));
+    assert_eq!(map(KeyCode::Key1, &modifiers), DecodedKey::Unicode('!'));
+    modifiers.capslock = true;
+    assert_eq!(map(KeyCode::Oem1, &modifiers), DecodedKey::Unicode('ж'));
+}
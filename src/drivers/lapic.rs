@@ -0,0 +1,127 @@
+use crate::memory;
+use conquer_once::spin::OnceCell;
+use x86_64::{
+    PhysAddr, VirtAddr,
+    instructions::interrupts::without_interrupts,
+    registers::model_specific::Msr,
+    structures::paging::{FrameAllocator, Mapper, Size4KiB},
+};
+
+const IA32_APIC_BASE: u32 = 0x1B;
+
+const ID: u64 = 0x020;
+const EOI: u64 = 0x0B0;
+const SPURIOUS: u64 = 0x0F0;
+const ICR_LOW: u64 = 0x300;
+const ICR_HIGH: u64 = 0x310;
+
+/// Software enable bit of the spurious interrupt vector register.
+const APIC_ENABLE: u32 = 1 << 8;
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+const ICR_INIT: u32 = 0b101 << 8;
+const ICR_STARTUP: u32 = 0b110 << 8;
+const ICR_ASSERT: u32 = 1 << 14;
+
+/// Vector of spurious LAPIC interrupts.
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+/// Vector of the IPI that wakes a halted CPU, see [`LocalApic::send_fixed`].
+pub const WAKEUP_VECTOR: u8 = 0xF0;
+
+static LAPIC: OnceCell<LocalApic> = OnceCell::uninit();
+
+/// Local APIC registers.
+///
+/// All CPUs map their own LAPIC at the same physical address, so one mapping
+/// serves every CPU and each access reaches the LAPIC of the calling CPU.
+pub struct LocalApic {
+    base: VirtAddr,
+}
+
+impl LocalApic {
+    /// APIC ID of the calling CPU.
+    pub fn id(&self) -> u8 {
+        (unsafe { self.read(ID) } >> 24) as u8
+    }
+
+    /// Software-enables the LAPIC of the calling CPU.
+    ///
+    /// The local vector table is left as the firmware set it up, so the
+    /// 8259 keeps delivering through LINT0 on the bootstrap processor.
+    pub fn enable(&self) {
+        unsafe {
+            let spurious = self.read(SPURIOUS) & !0xFF;
+            self.write(SPURIOUS, spurious | APIC_ENABLE | SPURIOUS_VECTOR as u32);
+        }
+    }
+
+    pub fn end_of_interrupt(&self) {
+        unsafe { self.write(EOI, 0) };
+    }
+
+    /// Sends an INIT IPI, which resets the target CPU into wait-for-SIPI.
+    pub fn send_init(&self, apic_id: u8) {
+        self.send_ipi(apic_id, ICR_INIT | ICR_ASSERT);
+    }
+
+    /// Sends a startup IPI, the target starts in real mode at
+    /// `page * 0x1000`.
+    pub fn send_startup(&self, apic_id: u8, page: u8) {
+        self.send_ipi(apic_id, ICR_STARTUP | ICR_ASSERT | page as u32);
+    }
+
+    /// Sends an interrupt with vector `vector` to the CPU with APIC ID
+    /// `apic_id`. The handler must signal the end of interrupt to its LAPIC.
+    pub fn send_fixed(&self, apic_id: u8, vector: u8) {
+        self.send_ipi(apic_id, ICR_ASSERT | vector as u32);
+    }
+
+    fn send_ipi(&self, apic_id: u8, command: u32) {
+        // an interrupt handler sending an IPI between the two writes would
+        // redirect this one
+        without_interrupts(|| unsafe {
+            self.write(ICR_HIGH, (apic_id as u32) << 24);
+            // writing the low half sends the IPI
+            self.write(ICR_LOW, command);
+            while self.read(ICR_LOW) & ICR_DELIVERY_PENDING != 0 {
+                core::hint::spin_loop();
+            }
+        })
+    }
+
+    unsafe fn read(&self, register: u64) -> u32 {
+        unsafe { (self.base + register).as_ptr::<u32>().read_volatile() }
+    }
+
+    unsafe fn write(&self, register: u64, value: u32) {
+        unsafe {
+            (self.base + register)
+                .as_mut_ptr::<u32>()
+                .write_volatile(value)
+        }
+    }
+}
+
+/// Maps the LAPIC registers and enables the LAPIC of the calling CPU.
+///
+/// Returns `None` if the mapping fails.
+pub fn init(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Option<&'static LocalApic> {
+    if let Ok(lapic) = LAPIC.try_get() {
+        return Some(lapic);
+    }
+
+    let address = unsafe { Msr::new(IA32_APIC_BASE).read() } & 0x000F_FFFF_FFFF_F000;
+    let base = memory::map_mmio(PhysAddr::new(address), 0x400, mapper, frame_allocator).ok()?;
+
+    let lapic = LocalApic { base };
+    lapic.enable();
+    LAPIC.init_once(|| lapic);
+    LAPIC.get()
+}
+
+/// The LAPIC, if [`init`] mapped it.
+pub fn get() -> Option<&'static LocalApic> {
+    LAPIC.get()
+}
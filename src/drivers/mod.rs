@@ -1,6 +1,12 @@
 pub mod serial;
 pub mod tty;
+pub mod input;
 pub mod keyboard;
+pub mod mouse;
+pub mod ps2;
 pub mod blockdev;
 pub mod ramdisk;
-pub mod rtc;
\ No newline at end of file
+pub mod rtc;
+pub mod pit;
+pub mod hpet;
+pub mod lapic;
\ No newline at end of file
@@ -0,0 +1,353 @@
+//! PS/2 mouse on the second port of the 8042 controller, IRQ12.
+//!
+//! Bytes are collected by the interrupt handler and decoded into packets in
+//! the bottom half. Mice that pass the IntelliMouse detection send 4-byte
+//! packets with a scroll wheel, all others 3-byte packets.
+
+use conquer_once::spin::OnceCell;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll};
+use futures_util::{ready, stream::Stream};
+use spin::Mutex;
+use x86_64::structures::idt::InterruptStackFrame;
+
+use super::input::{self, InputEvent, InputStream};
+use super::ps2::{self, Port};
+use super::tty::Color;
+use crate::interrupts::deferred::{self, Work};
+use crate::interrupts::{InterruptIndex, PICS};
+use crate::println;
+
+// mouse commands
+const SET_SAMPLE_RATE: u8 = 0xF3;
+const GET_ID: u8 = 0xF2;
+const ENABLE_REPORTING: u8 = 0xF4;
+const SET_DEFAULTS: u8 = 0xF6;
+const RESET: u8 = 0xFF;
+const RESET_PASSED: u8 = 0xAA;
+
+/// Device IDs of a plain mouse and of mice with a wheel.
+const ID_STANDARD: u8 = 0;
+const ID_INTELLIMOUSE: u8 = 3;
+const ID_EXPLORER: u8 = 4;
+
+/// Sample rates that unlock the wheel of an IntelliMouse.
+const INTELLIMOUSE_SEQUENCE: [u8; 3] = [200, 100, 80];
+
+const LEFT: u8 = 1 << 0;
+const RIGHT: u8 = 1 << 1;
+const MIDDLE: u8 = 1 << 2;
+/// Always set in the first byte of a packet.
+const ALWAYS_ONE: u8 = 1 << 3;
+const X_SIGN: u8 = 1 << 4;
+const Y_SIGN: u8 = 1 << 5;
+const X_OVERFLOW: u8 = 1 << 6;
+const Y_OVERFLOW: u8 = 1 << 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    Left,
+    Right,
+    Middle,
+}
+
+/// Driver event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseEvent {
+    /// Relative movement in screen directions: positive `dy` is down.
+    Move {
+        dx: i16,
+        dy: i16,
+    },
+    Press(Button),
+    Release(Button),
+    /// Wheel movement, positive is towards the user (scrolling down).
+    Scroll(i8),
+}
+
+/// State of the buttons.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Buttons {
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+}
+
+impl Buttons {
+    fn get(self, button: Button) -> bool {
+        match button {
+            Button::Left => self.left,
+            Button::Right => self.right,
+            Button::Middle => self.middle,
+        }
+    }
+}
+
+/// One decoded packet.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Packet {
+    pub dx: i16,
+    /// Positive is down, unlike on the wire.
+    pub dy: i16,
+    pub wheel: i8,
+    pub buttons: Buttons,
+}
+
+/// Assembles packets from the bytes of the mouse.
+pub struct PacketDecoder {
+    bytes: [u8; 4],
+    len: usize,
+    /// 3, or 4 with a wheel.
+    size: usize,
+}
+
+impl PacketDecoder {
+    pub fn new(wheel: bool) -> Self {
+        Self {
+            bytes: [0; 4],
+            len: 0,
+            size: if wheel { 4 } else { 3 },
+        }
+    }
+
+    /// Feeds one byte, returns a packet once it is complete.
+    pub fn add_byte(&mut self, byte: u8) -> Option<Packet> {
+        if self.len == 0 && byte & ALWAYS_ONE == 0 {
+            // out of sync, wait for something that looks like a first byte
+            return None;
+        }
+        self.bytes[self.len] = byte;
+        self.len += 1;
+        if self.len < self.size {
+            return None;
+        }
+        self.len = 0;
+
+        let [flags, x, y, extra] = self.bytes;
+        let axis = |value: u8, sign: u8, overflow: u8| {
+            if flags & overflow != 0 {
+                0
+            } else if flags & sign != 0 {
+                i16::from(value) - 0x100
+            } else {
+                i16::from(value)
+            }
+        };
+        Some(Packet {
+            dx: axis(x, X_SIGN, X_OVERFLOW),
+            dy: -axis(y, Y_SIGN, Y_OVERFLOW),
+            // a 4-bit two's complement value
+            wheel: if self.size == 4 {
+                ((extra << 4) as i8) >> 4
+            } else {
+                0
+            },
+            buttons: Buttons {
+                left: flags & LEFT != 0,
+                right: flags & RIGHT != 0,
+                middle: flags & MIDDLE != 0,
+            },
+        })
+    }
+}
+
+/// Global driver state
+pub struct MouseDriver {
+    decoder: Mutex<PacketDecoder>,
+    buttons: Mutex<Buttons>,
+    wheel: AtomicBool,
+}
+
+impl MouseDriver {
+    /// Resets the mouse, detects a wheel and enables reporting.
+    fn init() -> Result<Self, ps2::Error> {
+        ps2::enable_second_port()?;
+        let mut result = [0; 2];
+        ps2::query(Port::Second, &[RESET], &mut result, ps2::RESET_TIMEOUT)?;
+        if result != [RESET_PASSED, ID_STANDARD] {
+            return Err(ps2::Error::Unexpected(result[0]));
+        }
+        ps2::command(Port::Second, &[SET_DEFAULTS])?;
+        for rate in INTELLIMOUSE_SEQUENCE {
+            ps2::command(Port::Second, &[SET_SAMPLE_RATE, rate])?;
+        }
+        let mut id = [0];
+        ps2::query(Port::Second, &[GET_ID], &mut id, ps2::RESET_TIMEOUT)?;
+        let wheel = matches!(id[0], ID_INTELLIMOUSE | ID_EXPLORER);
+        ps2::command(Port::Second, &[ENABLE_REPORTING])?;
+
+        Ok(Self {
+            decoder: Mutex::new(PacketDecoder::new(wheel)),
+            buttons: Mutex::new(Buttons::default()),
+            wheel: AtomicBool::new(wheel),
+        })
+    }
+
+    /// Decodes a byte, called from the mouse bottom half
+    fn handle_byte(&self, byte: u8) {
+        let Some(packet) = self.decoder.lock().add_byte(byte) else {
+            return;
+        };
+        let previous = core::mem::replace(&mut *self.buttons.lock(), packet.buttons);
+        if packet.dx != 0 || packet.dy != 0 {
+            self.push(MouseEvent::Move {
+                dx: packet.dx,
+                dy: packet.dy,
+            });
+        }
+        for button in [Button::Left, Button::Right, Button::Middle] {
+            match (previous.get(button), packet.buttons.get(button)) {
+                (false, true) => self.push(MouseEvent::Press(button)),
+                (true, false) => self.push(MouseEvent::Release(button)),
+                _ => {}
+            }
+        }
+        if packet.wheel != 0 {
+            self.push(MouseEvent::Scroll(packet.wheel));
+        }
+    }
+
+    fn push(&self, event: MouseEvent) {
+        input::publish(InputEvent::Mouse(event));
+    }
+}
+
+static DRIVER: OnceCell<MouseDriver> = OnceCell::uninit();
+
+/// Sets up the mouse, if the controller has a second port.
+pub fn init_mouse() {
+    if !ps2::has_port(Port::Second) {
+        return;
+    }
+    match MouseDriver::init() {
+        Ok(driver) => {
+            DRIVER.init_once(move || driver);
+            crate::interrupts::unmask(InterruptIndex::Mouse);
+        }
+        Err(err) => {
+            println!(
+                (Color::Yellow, Color::Black),
+                "WARNING: mouse init failed: {:?}", err
+            );
+        }
+    }
+}
+
+/// Whether a mouse was found.
+pub fn is_present() -> bool {
+    DRIVER.is_initialized()
+}
+
+/// Whether the mouse has a scroll wheel.
+pub fn has_wheel() -> bool {
+    DRIVER
+        .try_get()
+        .is_ok_and(|driver| driver.wheel.load(Ordering::Relaxed))
+}
+
+/// Queues a byte for decoding in the bottom half.
+pub fn add_byte_from_irq(byte: u8) {
+    let _ = deferred::schedule(Work::new(process_byte, byte as u64));
+}
+
+/// Bottom half of the mouse IRQ, runs with interrupts enabled.
+fn process_byte(byte: u64) {
+    if let Ok(driver) = DRIVER.try_get() {
+        driver.handle_byte(byte as u8);
+    }
+}
+
+/// Mouse events of an input monitor, see [`input::monitor_mouse`].
+pub struct MouseStream {
+    input: InputStream,
+}
+
+impl MouseStream {
+    pub fn new() -> Self {
+        Self {
+            input: input::monitor_mouse(),
+        }
+    }
+}
+
+impl Default for MouseStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stream for MouseStream {
+    type Item = MouseEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match ready!(Pin::new(&mut self.input).poll_next(cx)) {
+                Some(InputEvent::Mouse(event)) => return Poll::Ready(Some(event)),
+                Some(_) => {}
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+pub extern "x86-interrupt" fn mouse_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    let _irq = crate::percpu::enter_interrupt();
+    crate::interrupts::stats::record(InterruptIndex::Mouse.as_u8());
+    if let Some(byte) = ps2::read_irq_byte(Port::Second) {
+        add_byte_from_irq(byte);
+    }
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Mouse.as_u8());
+    }
+}
+
+#[test_case]
+fn test_standard_packet() {
+    let mut decoder = PacketDecoder::new(false);
+    // left button, moved 5 right and 3 up
+    assert_eq!(decoder.add_byte(ALWAYS_ONE | LEFT), None);
+    assert_eq!(decoder.add_byte(5), None);
+    let packet = decoder.add_byte(3).unwrap();
+    assert_eq!((packet.dx, packet.dy), (5, -3));
+    assert!(packet.buttons.left && !packet.buttons.right);
+}
+
+#[test_case]
+fn test_negative_and_overflow() {
+    let mut decoder = PacketDecoder::new(false);
+    decoder.add_byte(ALWAYS_ONE | X_SIGN | Y_OVERFLOW);
+    decoder.add_byte(0xFE);
+    let packet = decoder.add_byte(0x7F).unwrap();
+    assert_eq!((packet.dx, packet.dy), (-2, 0));
+}
+
+#[test_case]
+fn test_resync() {
+    let mut decoder = PacketDecoder::new(false);
+    // a byte without the always-one bit cannot start a packet
+    assert_eq!(decoder.add_byte(0x00), None);
+    decoder.add_byte(ALWAYS_ONE | RIGHT);
+    decoder.add_byte(0);
+    assert!(decoder.add_byte(0).unwrap().buttons.right);
+}
+
+#[test_case]
+fn test_wheel_packet() {
+    let mut decoder = PacketDecoder::new(true);
+    decoder.add_byte(ALWAYS_ONE | MIDDLE);
+    decoder.add_byte(0);
+    assert_eq!(decoder.add_byte(0), None);
+    let packet = decoder.add_byte(0x0F).unwrap();
+    assert_eq!(packet.wheel, -1);
+    assert!(packet.buttons.middle);
+}
+
+#[test_case]
+fn test_mouse_present() {
+    // QEMU emulates an IntelliMouse on the second port
+    assert!(is_present());
+    assert!(has_wheel());
+}
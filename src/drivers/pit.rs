@@ -0,0 +1,50 @@
+use x86_64::instructions::port::Port;
+
+/// Input clock of the 8253/8254 PIT in Hz.
+pub const FREQUENCY: u64 = 1_193_182;
+
+const CHANNEL_0: u16 = 0x40;
+const CHANNEL_2: u16 = 0x42;
+const COMMAND: u16 = 0x43;
+/// Keyboard controller port B: channel 2 gate (bit 0), speaker (bit 1) and
+/// channel 2 output (bit 5).
+const PORT_B: u16 = 0x61;
+
+/// Busy-waits for `ticks` PIT cycles on channel 2.
+///
+/// Polls the channel output instead of relying on interrupts, so it can be
+/// used for calibration before interrupts are enabled.
+pub fn wait_ticks(ticks: u16) {
+    let mut port_b = Port::<u8>::new(PORT_B);
+    let mut command = Port::<u8>::new(COMMAND);
+    let mut channel_2 = Port::<u8>::new(CHANNEL_2);
+
+    unsafe {
+        // gate high, speaker off
+        let value = port_b.read();
+        port_b.write((value & !0x02) | 0x01);
+
+        // channel 2, lobyte/hibyte, mode 0 (interrupt on terminal count)
+        command.write(0b1011_0000);
+        channel_2.write(ticks as u8);
+        channel_2.write((ticks >> 8) as u8);
+
+        while port_b.read() & 0x20 == 0 {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Programs channel 0, which drives IRQ0, to fire `hz` times per second.
+pub fn set_periodic(hz: u32) {
+    let divisor = (FREQUENCY / hz as u64).clamp(1, u16::MAX as u64) as u16;
+    let mut command = Port::<u8>::new(COMMAND);
+    let mut channel_0 = Port::<u8>::new(CHANNEL_0);
+
+    unsafe {
+        // channel 0, lobyte/hibyte, mode 2 (rate generator)
+        command.write(0b0011_0100);
+        channel_0.write(divisor as u8);
+        channel_0.write((divisor >> 8) as u8);
+    }
+}
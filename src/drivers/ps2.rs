@@ -0,0 +1,350 @@
+//! 8042 PS/2 controller.
+//!
+//! [`init`] tests the controller and its ports and leaves the first port
+//! enabled with its interrupt on. Device commands are sent with [`command`]
+//! and [`query`], which poll the controller with interrupts enabled: the
+//! interrupt handlers leave the controller to a running command. Bytes that
+//! arrive from the devices in the meantime, such as key presses between a
+//! command and its ACK, are handed to the device's regular interrupt path.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port as IoPort;
+
+use crate::percpu;
+use crate::time::Instant;
+
+const DATA: u16 = 0x60;
+/// Status register on read, command register on write.
+const STATUS: u16 = 0x64;
+
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+/// The byte in the output buffer came from the second port.
+const STATUS_SECOND_PORT: u8 = 1 << 5;
+
+const READ_CONFIG: u8 = 0x20;
+const WRITE_CONFIG: u8 = 0x60;
+const DISABLE_SECOND: u8 = 0xA7;
+const ENABLE_SECOND: u8 = 0xA8;
+const TEST_SECOND: u8 = 0xA9;
+const SELF_TEST: u8 = 0xAA;
+const TEST_FIRST: u8 = 0xAB;
+const DISABLE_FIRST: u8 = 0xAD;
+const ENABLE_FIRST: u8 = 0xAE;
+const WRITE_SECOND: u8 = 0xD4;
+
+const CONFIG_FIRST_IRQ: u8 = 1 << 0;
+const CONFIG_SECOND_IRQ: u8 = 1 << 1;
+const CONFIG_SECOND_CLOCK_OFF: u8 = 1 << 5;
+
+const SELF_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+pub const ACK: u8 = 0xFA;
+pub const RESEND: u8 = 0xFE;
+
+/// How long the controller or a device may take to accept or answer a byte.
+const TIMEOUT: Duration = Duration::from_millis(20);
+/// Resets run the device's self-test, which takes much longer.
+pub const RESET_TIMEOUT: Duration = Duration::from_millis(750);
+/// Attempts per byte when the device asks for a resend.
+const RETRIES: usize = 3;
+
+/// Held for every access to the controller. Interrupt handlers only try to
+/// take it, see [`read_irq_byte`], so it may be held with interrupts enabled.
+static LOCK: spin::Mutex<()> = spin::Mutex::new(());
+static FIRST_PORT: AtomicBool = AtomicBool::new(false);
+static SECOND_PORT: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Port {
+    /// The keyboard port, IRQ1.
+    First,
+    /// The auxiliary (mouse) port, IRQ12.
+    Second,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The controller or the device did not respond in time.
+    Timeout,
+    /// The controller self-test returned this instead of 0x55.
+    SelfTest(u8),
+    /// The port test returned this error code.
+    PortTest(Port, u8),
+    /// The port is missing or failed its test.
+    NoPort(Port),
+    /// The device kept asking for a resend.
+    Resend,
+    /// The device answered a command with this unexpected byte.
+    Unexpected(u8),
+}
+
+/// Tests the controller and both ports and enables the first port and its
+/// interrupt.
+///
+/// The second port is tested but left disabled, see [`enable_second_port`].
+/// Fails only if the controller or the first port is unusable.
+pub fn init() -> Result<(), Error> {
+    without_interrupts(|| {
+        let _lock = LOCK.lock();
+        write_command(DISABLE_FIRST)?;
+        write_command(DISABLE_SECOND)?;
+        flush();
+
+        let mut config = read_config()?;
+        config &= !(CONFIG_FIRST_IRQ | CONFIG_SECOND_IRQ);
+        write_config(config)?;
+
+        write_command(SELF_TEST)?;
+        match read_byte(TIMEOUT)? {
+            SELF_TEST_PASSED => {}
+            result => return Err(Error::SelfTest(result)),
+        }
+        // the self-test may reset the controller
+        write_config(config)?;
+
+        // the second clock only turns on if the controller has a second port
+        write_command(ENABLE_SECOND)?;
+        let dual = read_config()? & CONFIG_SECOND_CLOCK_OFF == 0;
+        write_command(DISABLE_SECOND)?;
+
+        SECOND_PORT.store(dual && test_port(Port::Second).is_ok(), Ordering::Relaxed);
+        test_port(Port::First)?;
+        FIRST_PORT.store(true, Ordering::Relaxed);
+
+        write_command(ENABLE_FIRST)?;
+        write_config(config | CONFIG_FIRST_IRQ)
+    })
+}
+
+/// Whether [`init`] found `port` working.
+pub fn has_port(port: Port) -> bool {
+    match port {
+        Port::First => FIRST_PORT.load(Ordering::Relaxed),
+        Port::Second => SECOND_PORT.load(Ordering::Relaxed),
+    }
+}
+
+/// Enables the second port and its interrupt.
+pub fn enable_second_port() -> Result<(), Error> {
+    if !has_port(Port::Second) {
+        return Err(Error::NoPort(Port::Second));
+    }
+    without_interrupts(|| {
+        let _lock = LOCK.lock();
+        write_command(ENABLE_SECOND)?;
+        let config = read_config()?;
+        write_config(config | CONFIG_SECOND_IRQ)
+    })
+}
+
+/// Sends the command `bytes` to the device on `port`, waiting for the ACK
+/// of every byte and resending it if the device asks to.
+pub fn command(port: Port, bytes: &[u8]) -> Result<(), Error> {
+    query(port, bytes, &mut [], TIMEOUT)
+}
+
+/// Sends the command `bytes` like [`command`], then reads
+/// `response.len()` bytes of response, each within `timeout`.
+pub fn query(
+    port: Port,
+    bytes: &[u8],
+    response: &mut [u8],
+    timeout: Duration,
+) -> Result<(), Error> {
+    if !has_port(port) {
+        return Err(Error::NoPort(port));
+    }
+    let result = transfer(port, bytes, response, timeout);
+    forward_pending();
+    result
+}
+
+fn transfer(port: Port, bytes: &[u8], response: &mut [u8], timeout: Duration) -> Result<(), Error> {
+    // another thread on this CPU would spin on the lock until the timer
+    // switched back
+    let _preempt = percpu::disable_preemption();
+    let _lock = LOCK.lock();
+    for &byte in bytes {
+        send_acked(port, byte)?;
+    }
+    for slot in response {
+        *slot = read_from(port, timeout)?;
+    }
+    Ok(())
+}
+
+/// Hands bytes that arrived while the lock was held to their drivers: their
+/// interrupts found the controller busy and left them to the lock holder.
+fn forward_pending() {
+    while status() & STATUS_OUTPUT_FULL != 0 {
+        let Some(_lock) = LOCK.try_lock() else {
+            return; // the holder forwards them
+        };
+        while let Some((from, byte)) = read_any() {
+            forward(from, byte);
+        }
+    }
+}
+
+fn send_acked(port: Port, byte: u8) -> Result<(), Error> {
+    for _ in 0..RETRIES {
+        if port == Port::Second {
+            write_command(WRITE_SECOND)?;
+        }
+        write_data(byte)?;
+        if wait_ack(port)? {
+            return Ok(());
+        }
+    }
+    Err(Error::Resend)
+}
+
+/// Waits for the device on `port` to answer a command byte, returns false
+/// if it asks for a resend.
+///
+/// Other bytes from the device, like a key press sent before the command
+/// arrived, are handed to its interrupt path.
+fn wait_ack(port: Port) -> Result<bool, Error> {
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        match read_from(port, deadline.duration_since(Instant::now()))? {
+            ACK => return Ok(true),
+            RESEND => return Ok(false),
+            other => forward(port, other),
+        }
+    }
+}
+
+fn test_port(port: Port) -> Result<(), Error> {
+    write_command(match port {
+        Port::First => TEST_FIRST,
+        Port::Second => TEST_SECOND,
+    })?;
+    match read_byte(TIMEOUT)? {
+        PORT_TEST_PASSED => Ok(()),
+        code => Err(Error::PortTest(port, code)),
+    }
+}
+
+fn read_config() -> Result<u8, Error> {
+    write_command(READ_CONFIG)?;
+    read_byte(TIMEOUT)
+}
+
+fn write_config(config: u8) -> Result<(), Error> {
+    write_command(WRITE_CONFIG)?;
+    write_data(config)
+}
+
+fn status() -> u8 {
+    unsafe { IoPort::<u8>::new(STATUS).read() }
+}
+
+/// Discards whatever the devices sent before the controller was set up.
+fn flush() {
+    let mut data = IoPort::<u8>::new(DATA);
+    while status() & STATUS_OUTPUT_FULL != 0 {
+        let _ = unsafe { data.read() };
+    }
+}
+
+fn wait_status(mask: u8, set: bool) -> Result<u8, Error> {
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        let status = status();
+        if (status & mask != 0) == set {
+            return Ok(status);
+        }
+        if Instant::now() >= deadline {
+            return Err(Error::Timeout);
+        }
+        core::hint::spin_loop();
+    }
+}
+
+fn write_command(command: u8) -> Result<(), Error> {
+    wait_status(STATUS_INPUT_FULL, false)?;
+    unsafe { IoPort::<u8>::new(STATUS).write(command) };
+    Ok(())
+}
+
+fn write_data(byte: u8) -> Result<(), Error> {
+    wait_status(STATUS_INPUT_FULL, false)?;
+    unsafe { IoPort::<u8>::new(DATA).write(byte) };
+    Ok(())
+}
+
+/// Reads a controller response.
+fn read_byte(timeout: Duration) -> Result<u8, Error> {
+    let deadline = Instant::now() + timeout;
+    while status() & STATUS_OUTPUT_FULL == 0 {
+        if Instant::now() >= deadline {
+            return Err(Error::Timeout);
+        }
+        core::hint::spin_loop();
+    }
+    Ok(unsafe { IoPort::<u8>::new(DATA).read() })
+}
+
+/// Reads the next byte from the device on `port`, forwarding bytes from
+/// the other port to its interrupt path.
+fn read_from(port: Port, timeout: Duration) -> Result<u8, Error> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        match read_any() {
+            Some((from, byte)) if from == port => return Ok(byte),
+            Some((from, byte)) => forward(from, byte),
+            None if Instant::now() >= deadline => return Err(Error::Timeout),
+            None => {}
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Reads a byte from either device, if one is waiting.
+fn read_any() -> Option<(Port, u8)> {
+    let status = status();
+    if status & STATUS_OUTPUT_FULL == 0 {
+        return None;
+    }
+    let byte = unsafe { IoPort::<u8>::new(DATA).read() };
+    let from = if status & STATUS_SECOND_PORT != 0 {
+        Port::Second
+    } else {
+        Port::First
+    };
+    Some((from, byte))
+}
+
+/// Hands a byte read while waiting for another device to its driver.
+fn forward(port: Port, byte: u8) {
+    match port {
+        Port::First => super::keyboard::add_scancode_from_irq(byte),
+        Port::Second => super::mouse::add_byte_from_irq(byte),
+    }
+}
+
+/// Reads the byte that raised the interrupt of `port`.
+///
+/// Returns `None` if there is none: a command already consumed it while
+/// the interrupt was held off. Also returns `None` while a command runs,
+/// which reads the byte itself and hands it back to the interrupt path.
+pub fn read_irq_byte(port: Port) -> Option<u8> {
+    // the command may run on this CPU, waiting for it would never end
+    let _lock = LOCK.try_lock()?;
+    let status = status();
+    if status & STATUS_OUTPUT_FULL == 0
+        || (status & STATUS_SECOND_PORT != 0) != (port == Port::Second)
+    {
+        return None;
+    }
+    Some(unsafe { IoPort::<u8>::new(DATA).read() })
+}
+
+#[test_case]
+fn test_first_port_present() {
+    assert!(has_port(Port::First));
+}
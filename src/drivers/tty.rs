@@ -2,6 +2,11 @@ use core::fmt;
 use lazy_static::lazy_static;
 use spin::Mutex;
 use volatile::Volatile;
+use x86_64::instructions::port::Port;
+
+mod readline;
+
+pub use readline::{Readline, readline};
 
 lazy_static! {
     /// A global `Writer` instance that can be used for printing to the VGA text buffer.
@@ -62,6 +67,12 @@ const BUFFER_HEIGHT: usize = 25;
 /// The width of the text buffer (normally 80 columns).
 const BUFFER_WIDTH: usize = 80;
 
+/// CRT controller registers, which hold the hardware cursor position.
+const CRTC_INDEX: u16 = 0x3D4;
+const CRTC_DATA: u16 = 0x3D5;
+const CURSOR_LOCATION_HIGH: u8 = 0x0E;
+const CURSOR_LOCATION_LOW: u8 = 0x0F;
+
 /// A structure representing the VGA text buffer.
 #[repr(transparent)]
 struct Buffer {
@@ -109,13 +120,18 @@ impl Writer {
     /// support strings with non-ASCII characters, since they can't be printed in the VGA text
     /// mode.
     fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
-            match byte {
-                // printable ASCII byte or newline
-                0x20..=0x7e | b'\n' => self.write_byte(byte),
-                // not part of printable ASCII range
-                _ => self.write_byte(0xfe),
-            }
+        for c in s.chars() {
+            self.write_char(c);
+        }
+    }
+
+    /// Writes a character to the buffer, one cell per character.
+    fn write_char(&mut self, c: char) {
+        match c {
+            // printable ASCII byte or newline
+            ' '..='~' | '\n' => self.write_byte(c as u8),
+            // not part of printable ASCII range
+            _ => self.write_byte(0xfe),
         }
     }
 
@@ -154,6 +170,36 @@ impl Writer {
         f(self);
         self.color_code = old_color; // sets old color
     }
+
+    /// Column of the next character on the last row.
+    pub fn column(&self) -> usize {
+        self.column_position
+    }
+
+    /// Clears the last row from `column` on and continues writing there.
+    pub fn clear_from(&mut self, column: usize) {
+        let blank = ScreenChar {
+            ascii_character: b' ',
+            color_code: self.color_code,
+        };
+        for col in column..BUFFER_WIDTH {
+            self.buffer.chars[BUFFER_HEIGHT - 1][col].write(blank);
+        }
+        self.column_position = column.min(BUFFER_WIDTH);
+    }
+
+    /// Moves the blinking hardware cursor to `column` of the last row.
+    pub fn set_cursor(&mut self, column: usize) {
+        let position = ((BUFFER_HEIGHT - 1) * BUFFER_WIDTH + column.min(BUFFER_WIDTH - 1)) as u16;
+        let mut index = Port::<u8>::new(CRTC_INDEX);
+        let mut data = Port::<u8>::new(CRTC_DATA);
+        unsafe {
+            index.write(CURSOR_LOCATION_LOW);
+            data.write(position as u8);
+            index.write(CURSOR_LOCATION_HIGH);
+            data.write((position >> 8) as u8);
+        }
+    }
 }
 
 impl fmt::Write for Writer {
@@ -0,0 +1,395 @@
+//! Line editing on the console.
+//!
+//! [`readline`] reads a line from the keyboard while showing it on the last
+//! row of the screen, [`Readline`] reads several through one keyboard
+//! subscription. The line scrolls horizontally when it is wider than
+//! the space after the prompt.
+
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+use futures_util::StreamExt;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use super::{BUFFER_WIDTH, WRITER};
+use crate::drivers::keyboard::{DecodedKey, Key, KeyCode, KeyboardEvent, KeyboardStream};
+use crate::print;
+
+/// Lines kept in the history.
+const HISTORY_SIZE: usize = 64;
+/// Columns the line needs at least, the prompt goes on a new row otherwise.
+const MIN_WIDTH: usize = 16;
+
+/// Entered lines, the most recent last.
+static HISTORY: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// Prints `prompt` and reads a line with a new [`Readline`], see
+/// [`Readline::read`].
+///
+/// Keys typed after the line was entered are lost unless the caller reads
+/// the next line with the same [`Readline`].
+pub async fn readline(prompt: &str) -> String {
+    Readline::new().read(prompt).await
+}
+
+/// Reads lines from the keyboard.
+///
+/// Holds one keyboard subscription for all the lines it reads, so keys
+/// typed between two reads go to the next line instead of being lost.
+pub struct Readline {
+    keys: KeyboardStream,
+}
+
+impl Readline {
+    /// Subscribes to the keyboard and takes focus, see [`KeyboardStream::new`].
+    pub fn new() -> Self {
+        Self {
+            keys: KeyboardStream::new(),
+        }
+    }
+
+    /// Prints `prompt` and reads a line, which is returned without the
+    /// newline.
+    ///
+    /// Supported keys:
+    ///
+    /// - Left and Right move the cursor, Home and End to the ends of the line
+    /// - Backspace and Delete erase the character before and under the cursor
+    /// - Up and Down walk the history of entered lines
+    /// - Ctrl+U erases everything before the cursor, Ctrl+W the word before it
+    pub async fn read(&mut self, prompt: &str) -> String {
+        let mut editor = LineEditor::default();
+        let mut view = View::new(prompt);
+        view.render(&editor);
+
+        while let Some(event) = self.keys.next().await {
+            let KeyboardEvent::KeyPress(key) = event else {
+                continue;
+            };
+            match Edit::of(&key) {
+                Some(Edit::Submit) => break,
+                Some(edit) => {
+                    without_interrupts(|| editor.apply(edit, &HISTORY.lock()));
+                    view.render(&editor);
+                }
+                None => {}
+            }
+        }
+
+        print!("\n");
+        without_interrupts(|| WRITER.lock().set_cursor(0));
+        let line: String = editor.line.into_iter().collect();
+        add_history(&line);
+        line
+    }
+}
+
+impl Default for Readline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Remembers `line`, unless it is blank or repeats the previous one.
+fn add_history(line: &str) {
+    if line.trim().is_empty() {
+        return;
+    }
+    without_interrupts(|| {
+        let mut history = HISTORY.lock();
+        if history.back().is_some_and(|last| last == line) {
+            return;
+        }
+        if history.len() == HISTORY_SIZE {
+            history.pop_front();
+        }
+        history.push_back(String::from(line));
+    });
+}
+
+/// What a key press does to the line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit {
+    Insert(char),
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Home,
+    End,
+    /// Previous line of the history.
+    Up,
+    /// Next line of the history.
+    Down,
+    /// Ctrl+U
+    KillLine,
+    /// Ctrl+W
+    KillWord,
+    Submit,
+}
+
+impl Edit {
+    fn of(key: &Key) -> Option<Edit> {
+        if key.modifiers.ctrl {
+            // by key code, so the shortcuts work with any layout
+            return match key.code {
+                KeyCode::U => Some(Edit::KillLine),
+                KeyCode::W => Some(Edit::KillWord),
+                _ => None,
+            };
+        }
+        match key.decoded? {
+            DecodedKey::Unicode('\n') => Some(Edit::Submit),
+            DecodedKey::Unicode('\u{8}') => Some(Edit::Backspace),
+            DecodedKey::Unicode('\u{7f}') => Some(Edit::Delete),
+            DecodedKey::Unicode(c) if !c.is_control() => Some(Edit::Insert(c)),
+            DecodedKey::RawKey(KeyCode::ArrowLeft) => Some(Edit::Left),
+            DecodedKey::RawKey(KeyCode::ArrowRight) => Some(Edit::Right),
+            DecodedKey::RawKey(KeyCode::Home) => Some(Edit::Home),
+            DecodedKey::RawKey(KeyCode::End) => Some(Edit::End),
+            DecodedKey::RawKey(KeyCode::ArrowUp) => Some(Edit::Up),
+            DecodedKey::RawKey(KeyCode::ArrowDown) => Some(Edit::Down),
+            _ => None,
+        }
+    }
+}
+
+/// The line being edited, independent of the screen.
+#[derive(Default)]
+struct LineEditor {
+    line: Vec<char>,
+    /// Index in `line` the next character is inserted at.
+    cursor: usize,
+    /// Index of the history entry being shown, `None` for the new line.
+    recalled: Option<usize>,
+    /// The new line while a history entry is shown.
+    draft: Vec<char>,
+}
+
+impl LineEditor {
+    fn apply(&mut self, edit: Edit, history: &VecDeque<String>) {
+        match edit {
+            Edit::Insert(c) => {
+                self.line.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            Edit::Backspace => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                    self.line.remove(self.cursor);
+                }
+            }
+            Edit::Delete => {
+                if self.cursor < self.line.len() {
+                    self.line.remove(self.cursor);
+                }
+            }
+            Edit::Left => self.cursor = self.cursor.saturating_sub(1),
+            Edit::Right => self.cursor = (self.cursor + 1).min(self.line.len()),
+            Edit::Home => self.cursor = 0,
+            Edit::End => self.cursor = self.line.len(),
+            Edit::Up => {
+                let index = self.recalled.unwrap_or(history.len());
+                if index > 0 {
+                    if self.recalled.is_none() {
+                        self.draft = core::mem::take(&mut self.line);
+                    }
+                    self.recall(history, Some(index - 1));
+                }
+            }
+            Edit::Down => {
+                if let Some(index) = self.recalled {
+                    let next = Some(index + 1).filter(|&next| next < history.len());
+                    self.recall(history, next);
+                }
+            }
+            Edit::KillLine => {
+                self.line.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            Edit::KillWord => {
+                let before = &self.line[..self.cursor];
+                let spaces = before
+                    .iter()
+                    .rev()
+                    .take_while(|c| c.is_whitespace())
+                    .count();
+                let word = before[..before.len() - spaces]
+                    .iter()
+                    .rev()
+                    .take_while(|c| !c.is_whitespace())
+                    .count();
+                let start = self.cursor - spaces - word;
+                self.line.drain(start..self.cursor);
+                self.cursor = start;
+            }
+            Edit::Submit => {}
+        }
+    }
+
+    /// Shows history entry `index`, or the draft for `None`, with the
+    /// cursor at the end.
+    fn recall(&mut self, history: &VecDeque<String>, index: Option<usize>) {
+        self.line = match index.and_then(|index| history.get(index)) {
+            Some(entry) => entry.chars().collect(),
+            None => core::mem::take(&mut self.draft),
+        };
+        self.recalled = index;
+        self.cursor = self.line.len();
+    }
+}
+
+/// Where on the last row the line is shown.
+struct View {
+    /// Column right after the prompt.
+    column: usize,
+    /// Index of the first character shown.
+    offset: usize,
+}
+
+impl View {
+    /// Prints the prompt.
+    fn new(prompt: &str) -> Self {
+        let column = without_interrupts(|| {
+            let mut writer = WRITER.lock();
+            let prompt_width = prompt.chars().count();
+            if writer.column() + prompt_width + MIN_WIDTH > BUFFER_WIDTH {
+                writer.write_byte(b'\n');
+            }
+            for c in prompt.chars().take(BUFFER_WIDTH - MIN_WIDTH) {
+                writer.write_char(c);
+            }
+            writer.column()
+        });
+        View { column, offset: 0 }
+    }
+
+    /// Redraws the line and places the cursor.
+    fn render(&mut self, editor: &LineEditor) {
+        // the last column is left for the cursor behind the line
+        let width = BUFFER_WIDTH - 1 - self.column;
+        self.offset = self
+            .offset
+            .clamp(editor.cursor.saturating_sub(width), editor.cursor);
+        let end = editor.line.len().min(self.offset + width);
+        without_interrupts(|| {
+            let mut writer = WRITER.lock();
+            writer.clear_from(self.column);
+            for &c in &editor.line[self.offset..end] {
+                writer.write_char(c);
+            }
+            writer.set_cursor(self.column + editor.cursor - self.offset);
+        });
+    }
+}
+
+#[cfg(test)]
+fn edited(line: &str, edits: &[Edit]) -> (String, usize) {
+    let mut editor = LineEditor::default();
+    let history = VecDeque::new();
+    for c in line.chars() {
+        editor.apply(Edit::Insert(c), &history);
+    }
+    for &edit in edits {
+        editor.apply(edit, &history);
+    }
+    (editor.line.iter().collect(), editor.cursor)
+}
+
+#[test_case]
+fn test_cursor_movement() {
+    use Edit::*;
+
+    assert_eq!(edited("helo", &[Left, Insert('l')]), ("hello".into(), 4));
+    assert_eq!(
+        edited("ello", &[Home, Left, Insert('h')]),
+        ("hello".into(), 1)
+    );
+    assert_eq!(
+        edited("hell", &[Home, End, Right, Insert('o')]),
+        ("hello".into(), 5)
+    );
+}
+
+#[test_case]
+fn test_erase() {
+    use Edit::*;
+
+    assert_eq!(edited("hello", &[Backspace]), ("hell".into(), 4));
+    assert_eq!(
+        edited("hello", &[Home, Backspace, Delete]),
+        ("ello".into(), 0)
+    );
+    assert_eq!(edited("hello", &[Delete]), ("hello".into(), 5));
+}
+
+#[test_case]
+fn test_kill() {
+    use Edit::*;
+
+    assert_eq!(edited("ls -la  /tmp", &[Left, KillLine]), ("p".into(), 0));
+    assert_eq!(edited("ls -la  ", &[KillWord]), ("ls ".into(), 3));
+    assert_eq!(
+        edited("ls -la /tmp", &[Left, Left, KillWord]),
+        ("ls -la mp".into(), 7)
+    );
+    assert_eq!(edited("", &[KillWord]), ("".into(), 0));
+}
+
+#[test_case]
+fn test_history() {
+    let history: VecDeque<String> = ["first", "second"].map(String::from).into();
+    let mut editor = LineEditor::default();
+    editor.apply(Edit::Insert('x'), &history);
+
+    editor.apply(Edit::Up, &history);
+    assert_eq!(editor.line.iter().collect::<String>(), "second");
+    editor.apply(Edit::Up, &history);
+    editor.apply(Edit::Up, &history);
+    assert_eq!(editor.line.iter().collect::<String>(), "first");
+    assert_eq!(editor.cursor, 5);
+
+    editor.apply(Edit::Down, &history);
+    editor.apply(Edit::Down, &history);
+    // back to the line being typed
+    assert_eq!(editor.line, ['x']);
+    editor.apply(Edit::Down, &history);
+    assert_eq!(editor.line, ['x']);
+}
+
+#[test_case]
+fn test_keys() {
+    use crate::drivers::keyboard::Modifiers;
+
+    let key = |code, decoded, ctrl| Key {
+        code,
+        decoded,
+        modifiers: Modifiers {
+            ctrl,
+            ..Modifiers::default()
+        },
+    };
+    let unicode = |c| Some(DecodedKey::Unicode(c));
+    assert_eq!(
+        Edit::of(&key(KeyCode::A, unicode('ф'), false)),
+        Some(Edit::Insert('ф'))
+    );
+    assert_eq!(
+        Edit::of(&key(KeyCode::Return, unicode('\n'), false)),
+        Some(Edit::Submit)
+    );
+    assert_eq!(
+        Edit::of(&key(KeyCode::U, unicode('г'), true)),
+        Some(Edit::KillLine)
+    );
+    assert_eq!(Edit::of(&key(KeyCode::C, unicode('c'), true)), None);
+    assert_eq!(
+        Edit::of(&key(
+            KeyCode::Numpad4,
+            Some(DecodedKey::RawKey(KeyCode::ArrowLeft)),
+            false
+        )),
+        Some(Edit::Left)
+    );
+}
@@ -1,9 +1,16 @@
-use crate::{gdt, hlt_loop, println};
 use lazy_static::lazy_static;
 use pic8259::ChainedPics;
 use spin;
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::instructions::port::Port;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use crate::drivers::lapic;
+use crate::percpu;
 use crate::keyboard::keyboard_interrupt_handler;
+use crate::drivers::mouse::mouse_interrupt_handler;
+
+pub mod deferred;
+mod exceptions;
+pub mod stats;
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
@@ -13,10 +20,13 @@ pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
+    PrimarySpurious = PIC_1_OFFSET + 7,
+    Mouse = PIC_2_OFFSET + 4,
+    SecondarySpurious = PIC_2_OFFSET + 7,
 }
 
 impl InterruptIndex {
-    fn as_u8(self) -> u8 {
+    pub fn as_u8(self) -> u8 {
         self as u8
     }
 
@@ -31,15 +41,15 @@ pub static PICS: spin::Mutex<ChainedPics> =
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
-        idt.page_fault.set_handler_fn(page_fault_handler);
-        unsafe {
-            idt.double_fault
-                .set_handler_fn(double_fault_handler)
-                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
-        }
+        exceptions::install(&mut idt);
         idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
         idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
+        idt[InterruptIndex::Mouse.as_usize()].set_handler_fn(mouse_interrupt_handler);
+        idt[InterruptIndex::PrimarySpurious.as_usize()].set_handler_fn(primary_spurious_handler);
+        idt[InterruptIndex::SecondarySpurious.as_usize()]
+            .set_handler_fn(secondary_spurious_handler);
+        idt[usize::from(lapic::SPURIOUS_VECTOR)].set_handler_fn(lapic_spurious_handler);
+        idt[usize::from(lapic::WAKEUP_VECTOR)].set_handler_fn(wakeup_handler);
         idt
     };
 }
@@ -48,34 +58,100 @@ pub fn init_idt() {
     IDT.load();
 }
 
-extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
-    println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+/// Unmasks the IRQ of `index` on the PICs, along with the cascade for IRQs
+/// of the secondary PIC.
+pub fn unmask(index: InterruptIndex) {
+    let irq = index.as_u8() - PIC_1_OFFSET;
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut pics = PICS.lock();
+        unsafe {
+            let [mut primary, mut secondary] = pics.read_masks();
+            if irq < 8 {
+                primary &= !(1 << irq);
+            } else {
+                primary &= !(1 << 2);
+                secondary &= !(1 << (irq - 8));
+            }
+            pics.write_masks(primary, secondary);
+        }
+    });
 }
 
-extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
-    error_code: PageFaultErrorCode,
-) {
-    use x86_64::registers::control::Cr2;
-
-    println!("EXCEPTION: PAGE FAULT");
-    println!("Accessed Address: {:?}", Cr2::read());
-    println!("Error Code: {:?}", error_code);
-    println!("{:#?}", stack_frame);
-    hlt_loop();
+extern "x86-interrupt" fn timer_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let irq = percpu::enter_interrupt();
+    stats::record(InterruptIndex::Timer.as_u8());
+    crate::task::watchdog::check(&stack_frame);
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
+    }
+    // may switch to another thread, so it comes after the end of interrupt
+    // and after leaving interrupt context
+    drop(irq);
+    crate::thread::tick();
 }
 
-extern "x86-interrupt" fn double_fault_handler(
-    stack_frame: InterruptStackFrame,
-    _error_code: u64,
-) -> ! {
-    panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
-}
+const PIC_1_COMMAND: u16 = 0x20;
+const PIC_2_COMMAND: u16 = 0xA0;
+const PIC_READ_ISR: u8 = 0x0B;
+const PIC_EOI: u8 = 0x20;
 
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+/// Reads the in-service register of the PIC behind `command_port`.
+///
+/// Must be called with `PICS` locked so the OCW3 write and the read are not
+/// interleaved with other PIC accesses.
+fn read_isr(command_port: u16) -> u8 {
+    let mut port = Port::<u8>::new(command_port);
     unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
+        port.write(PIC_READ_ISR);
+        port.read()
+    }
+}
+
+/// IRQ7 fires spuriously when an interrupt is withdrawn before the CPU
+/// acknowledges it. In that case the ISR bit is clear and no EOI may be sent.
+extern "x86-interrupt" fn primary_spurious_handler(_stack_frame: InterruptStackFrame) {
+    let _irq = percpu::enter_interrupt();
+    stats::record(InterruptIndex::PrimarySpurious.as_u8());
+
+    let mut pics = PICS.lock();
+    if read_isr(PIC_1_COMMAND) & 0x80 == 0 {
+        stats::record_spurious();
+        return;
+    }
+    unsafe { pics.notify_end_of_interrupt(InterruptIndex::PrimarySpurious.as_u8()) };
+}
+
+/// A spurious IRQ15 still went through the cascade on the primary PIC, so
+/// the primary gets its EOI while the secondary must not receive one.
+extern "x86-interrupt" fn secondary_spurious_handler(_stack_frame: InterruptStackFrame) {
+    let _irq = percpu::enter_interrupt();
+    stats::record(InterruptIndex::SecondarySpurious.as_u8());
+
+    let mut pics = PICS.lock();
+    if read_isr(PIC_2_COMMAND) & 0x80 == 0 {
+        stats::record_spurious();
+        unsafe { Port::<u8>::new(PIC_1_COMMAND).write(PIC_EOI) };
+        return;
+    }
+    unsafe { pics.notify_end_of_interrupt(InterruptIndex::SecondarySpurious.as_u8()) };
+}
+
+/// The LAPIC raises its spurious vector for interrupts that vanished before
+/// delivery. These must not be acknowledged.
+extern "x86-interrupt" fn lapic_spurious_handler(_stack_frame: InterruptStackFrame) {
+    let _irq = percpu::enter_interrupt();
+    stats::record(lapic::SPURIOUS_VECTOR);
+    stats::record_spurious();
+}
+
+/// Sent by other CPUs to wake this one from `hlt`. There is nothing to do
+/// besides acknowledging it: the interrupted idle loop looks for work.
+extern "x86-interrupt" fn wakeup_handler(_stack_frame: InterruptStackFrame) {
+    let _irq = percpu::enter_interrupt();
+    stats::record(lapic::WAKEUP_VECTOR);
+    if let Some(lapic) = lapic::get() {
+        lapic.end_of_interrupt();
     }
 }
 
@@ -84,3 +160,9 @@ fn test_breakpoint_exception() {
     // invoke a breakpoint exception
     x86_64::instructions::interrupts::int3();
 }
+
+#[test_case]
+fn test_overflow_exception() {
+    // #OF is a trap, so execution resumes after the `int` instruction
+    unsafe { core::arch::asm!("int 4") };
+}
@@ -0,0 +1,143 @@
+//! Deferred interrupt work ("bottom halves").
+//!
+//! Interrupt handlers should only acknowledge the device and grab whatever
+//! data is volatile. Everything else is wrapped in a [`Work`] item and pushed
+//! into a lock-free queue with [`schedule`]; the queue is drained later with
+//! interrupts enabled, either by the [`run`] executor task or by an explicit
+//! call to [`run_pending`].
+
+use conquer_once::spin::OnceCell;
+use core::{
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll},
+};
+use crossbeam_queue::ArrayQueue;
+use futures_util::{
+    ready,
+    stream::{Stream, StreamExt},
+    task::AtomicWaker,
+};
+
+use crate::task::coop;
+
+const QUEUE_SIZE: usize = 256;
+
+static WORK_QUEUE: OnceCell<ArrayQueue<Work>> = OnceCell::uninit();
+static WAKER: AtomicWaker = AtomicWaker::new();
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// A unit of deferred work: a function and the argument to call it with.
+#[derive(Debug, Clone, Copy)]
+pub struct Work {
+    func: fn(u64),
+    arg: u64,
+}
+
+impl Work {
+    pub const fn new(func: fn(u64), arg: u64) -> Self {
+        Work { func, arg }
+    }
+
+    fn run(self) {
+        (self.func)(self.arg)
+    }
+}
+
+/// Allocates the work queue. Must be called after the heap is set up and
+/// before interrupts are enabled.
+pub fn init() {
+    WORK_QUEUE.init_once(|| ArrayQueue::new(QUEUE_SIZE));
+}
+
+/// Queues `work` for execution outside of interrupt context.
+///
+/// Safe to call from interrupt handlers: it neither allocates nor locks.
+/// If the queue is full or not yet initialized the work is handed back and
+/// counted as dropped.
+pub fn schedule(work: Work) -> Result<(), Work> {
+    let pushed = match WORK_QUEUE.try_get() {
+        Ok(queue) => queue.push(work),
+        Err(_) => Err(work),
+    };
+    match pushed {
+        Ok(()) => {
+            WAKER.wake();
+            Ok(())
+        }
+        Err(work) => {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+            Err(work)
+        }
+    }
+}
+
+/// Runs all currently queued work items and returns how many were run.
+pub fn run_pending() -> usize {
+    let Ok(queue) = WORK_QUEUE.try_get() else {
+        return 0;
+    };
+    let mut count = 0;
+    while let Some(work) = queue.pop() {
+        work.run();
+        count += 1;
+    }
+    count
+}
+
+/// Number of work items that could not be queued.
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// Executor task that runs deferred work as it arrives.
+///
+/// Meant to be spawned with [`Priority::BottomHalf`](crate::task::Priority).
+pub async fn run() {
+    let mut work_items = WorkStream { _private: () };
+    while let Some(work) = work_items.next().await {
+        work.run();
+    }
+}
+
+struct WorkStream {
+    _private: (),
+}
+
+impl Stream for WorkStream {
+    type Item = Work;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Work>> {
+        ready!(coop::poll_proceed(cx));
+        let queue = WORK_QUEUE.try_get().expect("work queue not initialized");
+
+        if let Some(work) = queue.pop() {
+            return Poll::Ready(Some(work));
+        }
+
+        WAKER.register(cx.waker());
+        match queue.pop() {
+            Some(work) => {
+                WAKER.take();
+                Poll::Ready(Some(work))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+#[test_case]
+fn test_run_pending() {
+    static SUM: AtomicU64 = AtomicU64::new(0);
+
+    fn add(value: u64) {
+        SUM.fetch_add(value, Ordering::Relaxed);
+    }
+
+    run_pending();
+    for i in 1..=4 {
+        schedule(Work::new(add, i)).expect("work queue full");
+    }
+    assert_eq!(run_pending(), 4);
+    assert_eq!(SUM.load(Ordering::Relaxed), 10);
+}
@@ -0,0 +1,188 @@
+use crate::{hlt_loop, println};
+use x86_64::registers::control::{Cr0, Cr2, Cr3, Cr4};
+use x86_64::structures::idt::{
+    InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode, SelectorErrorCode,
+};
+
+use super::stats;
+use crate::drivers::serial::SERIAL1;
+use crate::drivers::tty::WRITER;
+use crate::{backtrace, gdt};
+
+/// Error code pushed by the CPU, tagged with how it should be decoded.
+#[derive(Debug, Clone, Copy)]
+enum ErrorCode {
+    /// The exception does not push an error code.
+    None,
+    /// An error code without architectural structure.
+    Raw(u64),
+    /// A segment selector error code (#TS, #NP, #SS, #GP).
+    Selector(u64),
+}
+
+/// Installs handlers for all architectural exceptions into `idt`.
+pub(super) fn install(idt: &mut InterruptDescriptorTable) {
+    idt.divide_error.set_handler_fn(divide_error_handler);
+    idt.debug.set_handler_fn(debug_handler);
+    idt.non_maskable_interrupt.set_handler_fn(nmi_handler);
+    idt.breakpoint.set_handler_fn(breakpoint_handler);
+    idt.overflow.set_handler_fn(overflow_handler);
+    idt.bound_range_exceeded.set_handler_fn(bound_range_exceeded_handler);
+    idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+    idt.device_not_available.set_handler_fn(device_not_available_handler);
+    unsafe {
+        idt.double_fault
+            .set_handler_fn(double_fault_handler)
+            .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+    }
+    idt.invalid_tss.set_handler_fn(invalid_tss_handler);
+    idt.segment_not_present.set_handler_fn(segment_not_present_handler);
+    idt.stack_segment_fault.set_handler_fn(stack_segment_fault_handler);
+    idt.general_protection_fault.set_handler_fn(general_protection_fault_handler);
+    idt.page_fault.set_handler_fn(page_fault_handler);
+    idt.x87_floating_point.set_handler_fn(x87_floating_point_handler);
+    idt.alignment_check.set_handler_fn(alignment_check_handler);
+    idt.machine_check.set_handler_fn(machine_check_handler);
+    idt.simd_floating_point.set_handler_fn(simd_floating_point_handler);
+    idt.virtualization.set_handler_fn(virtualization_handler);
+    idt.cp_protection_exception.set_handler_fn(cp_protection_handler);
+    idt.hv_injection_exception.set_handler_fn(hv_injection_handler);
+    idt.vmm_communication_exception.set_handler_fn(vmm_communication_handler);
+    idt.security_exception.set_handler_fn(security_exception_handler);
+}
+
+/// Prints the exception name, its decoded error code, the interrupt stack frame
+/// and the control registers.
+fn dump_exception(name: &str, error_code: ErrorCode, stack_frame: &InterruptStackFrame) {
+    println!("EXCEPTION: {}", name);
+    match error_code {
+        ErrorCode::None => {}
+        ErrorCode::Raw(code) => println!("Error Code: {:#x}", code),
+        ErrorCode::Selector(code) => {
+            let selector = SelectorErrorCode::new_truncate(code);
+            if selector.is_null() {
+                println!("Error Code: 0 (not selector related)");
+            } else {
+                println!(
+                    "Error Code: {:#x} (index {}, table {:?}, external {})",
+                    code,
+                    selector.index(),
+                    selector.descriptor_table(),
+                    selector.external(),
+                );
+            }
+        }
+    }
+    println!("{:#?}", stack_frame);
+    dump_control_registers();
+}
+
+/// Prints CR0, CR3 and CR4 on a single line.
+fn dump_control_registers() {
+    let (cr3_frame, cr3_flags) = Cr3::read_raw();
+    println!(
+        "CR0={:#x} CR3={:#x} (flags {:#x}) CR4={:#x}",
+        Cr0::read_raw(),
+        cr3_frame.start_address().as_u64(),
+        cr3_flags,
+        Cr4::read_raw(),
+    );
+}
+
+/// Defines a handler for an exception that cannot be resumed: the state is
+/// dumped and the CPU is halted.
+macro_rules! fatal_handler {
+    ($handler:ident, $vector:expr, $name:expr) => {
+        extern "x86-interrupt" fn $handler(stack_frame: InterruptStackFrame) {
+            stats::record($vector);
+            dump_exception($name, ErrorCode::None, &stack_frame);
+            hlt_loop();
+        }
+    };
+    ($handler:ident, $vector:expr, $name:expr, $kind:ident) => {
+        extern "x86-interrupt" fn $handler(stack_frame: InterruptStackFrame, error_code: u64) {
+            stats::record($vector);
+            dump_exception($name, ErrorCode::$kind(error_code), &stack_frame);
+            hlt_loop();
+        }
+    };
+}
+
+/// Defines a handler for a trap-like exception: the state is dumped and
+/// execution continues after the faulting instruction.
+macro_rules! trap_handler {
+    ($handler:ident, $vector:expr, $name:expr) => {
+        extern "x86-interrupt" fn $handler(stack_frame: InterruptStackFrame) {
+            stats::record($vector);
+            dump_exception($name, ErrorCode::None, &stack_frame);
+        }
+    };
+}
+
+fatal_handler!(divide_error_handler, 0, "DIVIDE ERROR (#DE)");
+trap_handler!(debug_handler, 1, "DEBUG (#DB)");
+trap_handler!(overflow_handler, 4, "OVERFLOW (#OF)");
+fatal_handler!(bound_range_exceeded_handler, 5, "BOUND RANGE EXCEEDED (#BR)");
+fatal_handler!(invalid_opcode_handler, 6, "INVALID OPCODE (#UD)");
+fatal_handler!(device_not_available_handler, 7, "DEVICE NOT AVAILABLE (#NM)");
+fatal_handler!(invalid_tss_handler, 10, "INVALID TSS (#TS)", Selector);
+fatal_handler!(segment_not_present_handler, 11, "SEGMENT NOT PRESENT (#NP)", Selector);
+fatal_handler!(stack_segment_fault_handler, 12, "STACK SEGMENT FAULT (#SS)", Selector);
+fatal_handler!(general_protection_fault_handler, 13, "GENERAL PROTECTION FAULT (#GP)", Selector);
+fatal_handler!(x87_floating_point_handler, 16, "x87 FLOATING POINT (#MF)");
+fatal_handler!(alignment_check_handler, 17, "ALIGNMENT CHECK (#AC)", Raw);
+fatal_handler!(simd_floating_point_handler, 19, "SIMD FLOATING POINT (#XM)");
+fatal_handler!(virtualization_handler, 20, "VIRTUALIZATION (#VE)");
+fatal_handler!(cp_protection_handler, 21, "CONTROL PROTECTION (#CP)", Raw);
+fatal_handler!(hv_injection_handler, 28, "HYPERVISOR INJECTION (#HV)");
+fatal_handler!(vmm_communication_handler, 29, "VMM COMMUNICATION (#VC)", Raw);
+fatal_handler!(security_exception_handler, 30, "SECURITY EXCEPTION (#SX)", Raw);
+
+/// An NMI can arrive while this CPU holds the screen or serial lock, so the
+/// state is only printed through a lock that is free, never waited for.
+extern "x86-interrupt" fn nmi_handler(stack_frame: InterruptStackFrame) {
+    use core::fmt::Write;
+
+    stats::record(2);
+    let report = |out: &mut dyn Write| {
+        writeln!(out, "EXCEPTION: NON-MASKABLE INTERRUPT\n{:#?}", stack_frame)
+    };
+    if let Some(mut writer) = WRITER.try_lock() {
+        let _ = report(&mut *writer);
+    } else if let Some(mut serial) = SERIAL1.try_lock() {
+        let _ = report(&mut *serial);
+    }
+}
+
+extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
+    stats::record(3);
+    println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+}
+
+extern "x86-interrupt" fn page_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    stats::record(14);
+    println!("EXCEPTION: PAGE FAULT");
+    println!("Accessed Address: {:?}", Cr2::read());
+    println!("Error Code: {:?}", error_code);
+    println!("{:#?}", stack_frame);
+    dump_control_registers();
+    backtrace::print_interrupted(&stack_frame);
+    hlt_loop();
+}
+
+extern "x86-interrupt" fn double_fault_handler(
+    stack_frame: InterruptStackFrame,
+    _error_code: u64,
+) -> ! {
+    stats::record(8);
+    backtrace::print_interrupted(&stack_frame);
+    panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
+}
+
+extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+    stats::record(18);
+    panic!("EXCEPTION: MACHINE CHECK (#MC)\n{:#?}", stack_frame);
+}
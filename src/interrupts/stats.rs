@@ -0,0 +1,126 @@
+//! Per-vector interrupt counters, in the spirit of Linux's `/proc/interrupts`.
+
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use super::InterruptIndex;
+use crate::drivers::lapic;
+
+static COUNTS: [AtomicU64; 256] = [const { AtomicU64::new(0) }; 256];
+static SPURIOUS: AtomicU64 = AtomicU64::new(0);
+
+/// Number of times a single vector fired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptCount {
+    pub vector: u8,
+    pub name: &'static str,
+    pub count: u64,
+}
+
+impl fmt::Display for InterruptCount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:>4}: {:>10}  {}", self.vector, self.count, self.name)
+    }
+}
+
+/// Counts one occurrence of `vector`. Called on entry of every handler.
+pub fn record(vector: u8) {
+    COUNTS[vector as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counts a spurious IRQ from the 8259 or the LAPIC.
+pub(super) fn record_spurious() {
+    SPURIOUS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Number of times `vector` fired since boot, spurious IRQs included.
+pub fn count(vector: u8) -> u64 {
+    COUNTS[vector as usize].load(Ordering::Relaxed)
+}
+
+/// Number of spurious IRQ7/IRQ15 and LAPIC interrupts since boot.
+pub fn spurious() -> u64 {
+    SPURIOUS.load(Ordering::Relaxed)
+}
+
+/// Counters of every vector that fired at least once, ordered by vector.
+pub fn counts() -> Vec<InterruptCount> {
+    (0..=u8::MAX)
+        .filter_map(|vector| {
+            let count = count(vector);
+            (count != 0).then(|| InterruptCount {
+                vector,
+                name: vector_name(vector),
+                count,
+            })
+        })
+        .collect()
+}
+
+/// Prints the counters as a table, one vector per line.
+pub fn print_interrupts() {
+    for entry in counts() {
+        crate::println!("{}", entry);
+    }
+    crate::println!(" SPU: {:>10}  spurious interrupts", spurious());
+}
+
+/// Human-readable name of a vector.
+pub fn vector_name(vector: u8) -> &'static str {
+    const PRIMARY_SPURIOUS: u8 = InterruptIndex::PrimarySpurious as u8;
+    const SECONDARY_SPURIOUS: u8 = InterruptIndex::SecondarySpurious as u8;
+    const TIMER: u8 = InterruptIndex::Timer as u8;
+    const KEYBOARD: u8 = InterruptIndex::Keyboard as u8;
+    const MOUSE: u8 = InterruptIndex::Mouse as u8;
+
+    match vector {
+        0 => "#DE divide error",
+        1 => "#DB debug",
+        2 => "NMI",
+        3 => "#BP breakpoint",
+        4 => "#OF overflow",
+        5 => "#BR bound range exceeded",
+        6 => "#UD invalid opcode",
+        7 => "#NM device not available",
+        8 => "#DF double fault",
+        10 => "#TS invalid TSS",
+        11 => "#NP segment not present",
+        12 => "#SS stack segment fault",
+        13 => "#GP general protection",
+        14 => "#PF page fault",
+        16 => "#MF x87 floating point",
+        17 => "#AC alignment check",
+        18 => "#MC machine check",
+        19 => "#XM SIMD floating point",
+        20 => "#VE virtualization",
+        21 => "#CP control protection",
+        28 => "#HV hypervisor injection",
+        29 => "#VC VMM communication",
+        30 => "#SX security",
+        TIMER => "timer",
+        KEYBOARD => "keyboard",
+        MOUSE => "mouse",
+        PRIMARY_SPURIOUS => "IRQ7",
+        SECONDARY_SPURIOUS => "IRQ15",
+        lapic::SPURIOUS_VECTOR => "LAPIC spurious",
+        lapic::WAKEUP_VECTOR => "wakeup IPI",
+        _ => "unknown",
+    }
+}
+
+#[test_case]
+fn test_breakpoint_counted() {
+    let before = count(3);
+    x86_64::instructions::interrupts::int3();
+    assert_eq!(count(3), before + 1);
+}
+
+#[test_case]
+fn test_timer_counted() {
+    let before = count(InterruptIndex::Timer.as_u8());
+    while count(InterruptIndex::Timer.as_u8()) == before {
+        x86_64::instructions::hlt();
+    }
+    assert!(counts().iter().any(|c| c.name == "timer"));
+}
@@ -8,25 +8,38 @@
 extern crate alloc;
 use core::panic::PanicInfo;
 
+pub mod acpi;
 pub mod allocator;
+pub mod backtrace;
 pub mod gdt;
 pub mod interrupts;
 pub mod memory;
+pub mod percpu;
+pub mod smp;
 pub mod drivers;
 pub mod task;
+pub mod thread;
+pub mod time;
 
 use x86_64::structures::paging::Mapper;
 use crate::memory::BootInfoFrameAllocator;
 
 pub fn init(mapper: &mut impl Mapper<Size4KiB>, frame_allocator: &mut BootInfoFrameAllocator) {
+    percpu::init(0);
+    backtrace::init(frame_allocator.memory_map());
     gdt::init();
     interrupts::init_idt();
     unsafe { interrupts::PICS.lock().initialize() };
 
     allocator::init_heap(mapper, frame_allocator).expect("heap initialization failed");
+    interrupts::deferred::init();
+    time::init(mapper, frame_allocator);
+    thread::init();
+    smp::init(mapper, frame_allocator);
     x86_64::instructions::interrupts::enable();
 
     keyboard::init_keyboard();
+    mouse::init_mouse();
 }
 
 pub trait Testable {
@@ -55,6 +68,7 @@ pub fn test_runner(tests: &[&dyn Testable]) {
 pub fn test_panic_handler(info: &PanicInfo) -> ! {
     serial_println!("[failed]\n");
     serial_println!("Error: {}\n", info);
+    backtrace::print();
     exit_qemu(QemuExitCode::Failed);
     hlt_loop();
 }
@@ -93,7 +107,7 @@ macro_rules! ktrace {
 use bootloader::{BootInfo, entry_point};
 use x86_64::structures::paging::{Size4KiB};
 
-use crate::drivers::keyboard;
+use crate::drivers::{keyboard, mouse};
 
 #[cfg(test)]
 entry_point!(test_kernel_main);
@@ -6,13 +6,15 @@
 
 extern crate alloc;
 
-use kos::task::{keyboard, Task};
+use kos::task::{keyboard, Builder, Priority};
 use kos::{println};
 use kos::task::{executor::Executor};
 use bootloader::{BootInfo, entry_point};
 use core::panic::PanicInfo;
 use kos::drivers::tty::Color;
 use kos::drivers::rtc::Rtc;
+use kos::interrupts::deferred;
+use kos::{smp, thread};
 
 entry_point!(kernel_main);
 
@@ -34,16 +36,36 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     #[cfg(test)]
     test_main();
 
+    thread::Builder::new().name("executor").spawn(run_executor);
+    smp::run_on_aps(run_ap_executor);
+    thread::exit();
+}
+
+/// Body of the executor thread.
+fn run_executor() {
     let mut executor = Executor::new();
-    executor.spawn(Task::new(keyboard::print_keypresses()));
+    executor.spawner().set_global();
+    Builder::new()
+        .name("deferred")
+        .priority(Priority::BottomHalf)
+        .spawn(&mut executor, deferred::run());
+    Builder::new()
+        .name("console")
+        .spawn(&mut executor, keyboard::echo_lines());
     executor.run();
 }
 
+/// Main function of the APs, they poll tasks stolen from the BSP.
+fn run_ap_executor() -> ! {
+    Executor::new().run()
+}
+
 /// This function is called on panic.
 #[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     println!((Color::Red, Color::Black), "{}", info);
+    kos::backtrace::print();
     kos::hlt_loop();
 }
 
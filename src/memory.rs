@@ -1,9 +1,19 @@
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use core::sync::atomic::{AtomicU64, Ordering};
 use x86_64::{
     PhysAddr, VirtAddr,
-    structures::paging::{FrameAllocator, OffsetPageTable, PageTable, PhysFrame, Size4KiB},
+    structures::paging::{
+        FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame,
+        Size4KiB, mapper::MapToError,
+    },
 };
 
+/// Start of the virtual window used for memory-mapped device registers.
+pub const MMIO_START: u64 = 0x_5555_0000_0000;
+
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+static NEXT_MMIO_PAGE: AtomicU64 = AtomicU64::new(MMIO_START);
+
 /// Initialize a new OffsetPageTable.
 ///
 /// This function is unsafe because the caller must guarantee that the
@@ -11,12 +21,41 @@ use x86_64::{
 /// `physical_memory_offset`. Also, this function must be only called once
 /// to avoid aliasing `&mut` references (which is undefined behavior).
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    PHYSICAL_MEMORY_OFFSET.store(physical_memory_offset.as_u64(), Ordering::Relaxed);
     unsafe {
         let level_4_table = active_level_4_table(physical_memory_offset);
         OffsetPageTable::new(level_4_table, physical_memory_offset)
     }
 }
 
+/// Translates a physical address through the complete physical memory mapping
+/// set up by the bootloader.
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    VirtAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed) + addr.as_u64())
+}
+
+/// Maps `size` bytes of device registers at `addr` as uncached memory and
+/// returns the virtual address of `addr`.
+pub fn map_mmio(
+    addr: PhysAddr,
+    size: u64,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    let first_frame = PhysFrame::<Size4KiB>::containing_address(addr);
+    let last_frame = PhysFrame::<Size4KiB>::containing_address(addr + (size.max(1) - 1));
+    let frames = PhysFrame::range_inclusive(first_frame, last_frame);
+    let window = NEXT_MMIO_PAGE.fetch_add(frames.count() as u64 * 4096, Ordering::Relaxed);
+
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE;
+    for (i, frame) in PhysFrame::range_inclusive(first_frame, last_frame).enumerate() {
+        let page = Page::containing_address(VirtAddr::new(window + i as u64 * 4096));
+        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+    }
+
+    Ok(VirtAddr::new(window + (addr - first_frame.start_address())))
+}
+
 /// Returns a mutable reference to the active level 4 table.
 ///
 /// This function is unsafe because the caller must guarantee that the
@@ -44,10 +83,16 @@ unsafe impl FrameAllocator<Size4KiB> for EmptyFrameAllocator {
     }
 }
 
+/// End of the first MiB. [`BootInfoFrameAllocator::allocate_frame`] never
+/// hands out frames below it, they are kept for code that has to run in real
+/// mode, like the SMP trampoline.
+const LOW_MEMORY_END: u64 = 0x10_0000;
+
 /// A FrameAllocator that returns usable frames from the bootloader's memory map.
 pub struct BootInfoFrameAllocator {
     memory_map: &'static MemoryMap,
     next: usize,
+    next_low: usize,
 }
 
 impl BootInfoFrameAllocator {
@@ -60,20 +105,43 @@ impl BootInfoFrameAllocator {
         BootInfoFrameAllocator {
             memory_map,
             next: 0,
+            next_low: 0,
         }
     }
 
-    /// Returns an iterator over the usable frames specified in the memory map.
+    pub fn memory_map(&self) -> &'static MemoryMap {
+        self.memory_map
+    }
+
+    /// Allocates a usable frame below 1 MiB, see [`LOW_MEMORY_END`].
+    pub fn allocate_low_frame(&mut self) -> Option<PhysFrame> {
+        let frame = self
+            .usable_addresses()
+            // frame 0 holds the real mode IVT and BIOS data area
+            .filter(|&addr| addr != 0 && addr < LOW_MEMORY_END)
+            .nth(self.next_low);
+        self.next_low += 1;
+        frame.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+    }
+
+    /// Returns an iterator over the usable frames above 1 MiB specified in
+    /// the memory map.
     fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
+        self.usable_addresses()
+            .filter(|&addr| addr >= LOW_MEMORY_END)
+            // create `PhysFrame` types from the start addresses
+            .map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+    }
+
+    /// Returns an iterator over the start addresses of all usable frames.
+    fn usable_addresses(&self) -> impl Iterator<Item = u64> {
         // get usable regions from memory map
         let regions = self.memory_map.iter();
         let usable_regions = regions.filter(|r| r.region_type == MemoryRegionType::Usable);
         // map each region to its address range
         let addr_ranges = usable_regions.map(|r| r.range.start_addr()..r.range.end_addr());
         // transform to an iterator of frame start addresses
-        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
-        // create `PhysFrame` types from the start addresses
-        frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+        addr_ranges.flat_map(|r| r.step_by(4096))
     }
 }
 
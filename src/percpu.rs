@@ -0,0 +1,217 @@
+//! Per-CPU data.
+//!
+//! Every CPU points its GS base at its own [`Header`], so the current CPU's
+//! data is one `gs:`-relative load away. The header holds the CPU number,
+//! the interrupt nesting depth and the preemption-disable count; arbitrary
+//! per-CPU statics are declared with [`cpu_local!`](crate::cpu_local).
+//!
+//! GS-relative accesses fault until [`init`] ran on the CPU, so it comes
+//! first in [`crate::init`] and in the AP entry.
+
+use crate::smp::MAX_CPUS;
+use core::arch::asm;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use x86_64::VirtAddr;
+use x86_64::registers::model_specific::GsBase;
+
+/// Start of every CPU's data area, addressed through GS.
+///
+/// The offsets of the fields are used by `gs:` accesses, keep them in sync.
+#[repr(C)]
+struct Header {
+    /// Address of the header itself, at `gs:0`.
+    this: AtomicPtr<Header>,
+    /// At `gs:8`.
+    cpu: AtomicUsize,
+    /// At `gs:16`.
+    interrupt_depth: AtomicUsize,
+    /// At `gs:24`.
+    preempt_count: AtomicUsize,
+}
+
+impl Header {
+    const fn new() -> Header {
+        Header {
+            this: AtomicPtr::new(core::ptr::null_mut()),
+            cpu: AtomicUsize::new(0),
+            interrupt_depth: AtomicUsize::new(0),
+            preempt_count: AtomicUsize::new(0),
+        }
+    }
+}
+
+static HEADERS: [Header; MAX_CPUS] = [const { Header::new() }; MAX_CPUS];
+
+/// Points the GS base of the calling CPU at the data of CPU `cpu`.
+pub fn init(cpu: usize) {
+    let header = &HEADERS[cpu];
+    header
+        .this
+        .store(header as *const Header as *mut Header, Ordering::Relaxed);
+    header.cpu.store(cpu, Ordering::Relaxed);
+    GsBase::write(VirtAddr::from_ptr(header));
+}
+
+/// Number of the calling CPU, 0 for the bootstrap processor.
+#[inline]
+pub fn current_cpu() -> usize {
+    let cpu: usize;
+    unsafe { asm!("mov {}, gs:[8]", out(reg) cpu, options(nostack, readonly, preserves_flags)) };
+    cpu
+}
+
+/// Number of interrupt handlers the calling CPU is currently inside.
+#[inline]
+pub fn interrupt_depth() -> usize {
+    let depth: usize;
+    unsafe { asm!("mov {}, gs:[16]", out(reg) depth, options(nostack, readonly, preserves_flags)) };
+    depth
+}
+
+pub fn in_interrupt() -> bool {
+    interrupt_depth() != 0
+}
+
+/// Marks the calling CPU as inside an interrupt handler until the guard is
+/// dropped. Taken at the top of every IRQ handler.
+pub fn enter_interrupt() -> InterruptGuard {
+    unsafe { asm!("inc qword ptr gs:[16]", options(nostack)) };
+    InterruptGuard(())
+}
+
+/// Returned by [`enter_interrupt`].
+pub struct InterruptGuard(());
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        unsafe { asm!("dec qword ptr gs:[16]", options(nostack)) };
+    }
+}
+
+/// Nesting count of [`disable_preemption`] on the calling CPU.
+#[inline]
+pub fn preempt_count() -> usize {
+    let count: usize;
+    unsafe { asm!("mov {}, gs:[24]", out(reg) count, options(nostack, readonly, preserves_flags)) };
+    count
+}
+
+/// Whether the timer may switch threads on the calling CPU.
+pub fn preemptible() -> bool {
+    preempt_count() == 0 && !in_interrupt()
+}
+
+/// Keeps the timer from switching threads on the calling CPU until the
+/// guard is dropped. Nests.
+pub fn disable_preemption() -> PreemptGuard {
+    unsafe { asm!("inc qword ptr gs:[24]", options(nostack)) };
+    PreemptGuard(())
+}
+
+/// Returned by [`disable_preemption`].
+pub struct PreemptGuard(());
+
+impl Drop for PreemptGuard {
+    fn drop(&mut self) {
+        unsafe { asm!("dec qword ptr gs:[24]", options(nostack)) };
+    }
+}
+
+/// A value with one instance per CPU, declared with
+/// [`cpu_local!`](crate::cpu_local).
+///
+/// Each CPU only reaches its own instance through [`get`](CpuLocal::get).
+/// Interrupt handlers on the same CPU share it with the interrupted code, so
+/// values that both touch need atomics or must be accessed with interrupts
+/// disabled.
+pub struct CpuLocal<T> {
+    slots: [T; MAX_CPUS],
+}
+
+// every CPU accesses only its own slot, unless `T: Sync`
+unsafe impl<T: Send> Sync for CpuLocal<T> {}
+
+impl<T> CpuLocal<T> {
+    #[doc(hidden)]
+    pub const fn new(slots: [T; MAX_CPUS]) -> Self {
+        CpuLocal { slots }
+    }
+
+    /// The calling CPU's instance.
+    ///
+    /// Threads do not migrate between CPUs, so the reference stays the
+    /// calling CPU's.
+    pub fn get(&self) -> &T {
+        &self.slots[current_cpu()]
+    }
+}
+
+impl<T: Sync> CpuLocal<T> {
+    /// The instance of CPU `cpu`.
+    pub fn for_cpu(&self, cpu: usize) -> &T {
+        &self.slots[cpu]
+    }
+}
+
+/// Declares a per-CPU static of type [`CpuLocal<T>`](crate::percpu::CpuLocal).
+///
+/// The initializer must be a constant expression; every CPU's instance
+/// starts as a copy of it.
+///
+/// ```ignore
+/// cpu_local! {
+///     static TICKS: AtomicU64 = AtomicU64::new(0);
+/// }
+///
+/// TICKS.get().fetch_add(1, Ordering::Relaxed);
+/// ```
+#[macro_export]
+macro_rules! cpu_local {
+    ($($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr;)+) => {
+        $(
+            $(#[$attr])*
+            $vis static $name: $crate::percpu::CpuLocal<$ty> =
+                $crate::percpu::CpuLocal::new([const { $init }; $crate::smp::MAX_CPUS]);
+        )+
+    };
+}
+
+#[test_case]
+fn test_bsp_is_cpu_0() {
+    assert_eq!(current_cpu(), 0);
+}
+
+#[test_case]
+fn test_interrupt_depth() {
+    assert!(!in_interrupt());
+    let outer = enter_interrupt();
+    let inner = enter_interrupt();
+    assert_eq!(interrupt_depth(), 2);
+    drop(inner);
+    drop(outer);
+    assert_eq!(interrupt_depth(), 0);
+}
+
+#[test_case]
+fn test_preempt_count_nests() {
+    assert!(preemptible());
+    let outer = disable_preemption();
+    let inner = disable_preemption();
+    assert_eq!(preempt_count(), 2);
+    drop(inner);
+    assert!(!preemptible());
+    drop(outer);
+    assert!(preemptible());
+}
+
+#[test_case]
+fn test_cpu_local() {
+    use core::cell::Cell;
+
+    crate::cpu_local! {
+        static VALUE: Cell<u32> = Cell::new(7);
+    }
+    assert_eq!(VALUE.get().get(), 7);
+    VALUE.get().set(8);
+    assert_eq!(VALUE.get().get(), 8);
+}
@@ -0,0 +1,196 @@
+//! Symmetric multiprocessing: starting the application processors.
+//!
+//! The CPUs are listed by the ACPI MADT. The bootstrap processor (BSP)
+//! starts every application processor (AP) in turn with the INIT-SIPI-SIPI
+//! sequence through its LAPIC. Each AP gets its own kernel stack, GDT and
+//! TSS, loads the shared IDT and then halts with interrupts enabled until
+//! [`run_on_aps`] gives it something to run.
+//!
+//! CPUs are numbered in start order: the BSP is CPU 0.
+
+mod trampoline;
+
+use crate::drivers::lapic::{self, LocalApic};
+use crate::memory::BootInfoFrameAllocator;
+use crate::{acpi, gdt, interrupts, percpu, println, time};
+use alloc::{boxed::Box, vec, vec::Vec};
+use conquer_once::spin::OnceCell;
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+use core::time::Duration;
+use trampoline::Trampoline;
+use x86_64::VirtAddr;
+use x86_64::structures::paging::{Mapper, Size4KiB};
+
+/// Upper bound on the number of CPUs brought online.
+pub const MAX_CPUS: usize = 16;
+
+/// Kernel stack size of every AP.
+const AP_STACK_SIZE: usize = 32 * 1024;
+
+/// Offset of the first entry in the MADT, after the SDT header, the LAPIC
+/// address and the flags.
+const MADT_ENTRIES: u64 = 44;
+const MADT_LOCAL_APIC: u8 = 0;
+const LOCAL_APIC_ENABLED: u32 = 1 << 0;
+const LOCAL_APIC_ONLINE_CAPABLE: u32 = 1 << 1;
+
+static ONLINE: AtomicUsize = AtomicUsize::new(1);
+static APIC_IDS: [AtomicU8; MAX_CPUS] = [const { AtomicU8::new(0) }; MAX_CPUS];
+/// Set by an AP once it runs on its own tables.
+static STARTED: AtomicBool = AtomicBool::new(false);
+/// What the APs run once they are up, see [`run_on_aps`].
+static AP_MAIN: OnceCell<fn() -> !> = OnceCell::uninit();
+
+/// Number of CPUs running, the BSP included.
+pub fn online_cpus() -> usize {
+    ONLINE.load(Ordering::Acquire)
+}
+
+/// LAPIC ID of CPU `cpu`, if it is online.
+pub fn apic_id(cpu: usize) -> Option<u8> {
+    (cpu < online_cpus()).then(|| APIC_IDS[cpu].load(Ordering::Relaxed))
+}
+
+/// LAPIC IDs of the usable CPUs listed in the MADT.
+pub fn present_apic_ids() -> Vec<u8> {
+    let Some(madt) = acpi::find_table(b"APIC") else {
+        return Vec::new();
+    };
+    let length = unsafe { acpi::read_header(madt) }.length as u64;
+    let mut ids = Vec::new();
+    let mut offset = MADT_ENTRIES;
+    while offset + 2 <= length {
+        let entry = crate::memory::phys_to_virt(madt + offset).as_ptr::<u8>();
+        let (kind, len) = unsafe { (*entry, *entry.add(1)) };
+        if len < 2 {
+            break;
+        }
+        if kind == MADT_LOCAL_APIC && len >= 8 {
+            let (apic_id, flags) =
+                unsafe { (*entry.add(3), entry.add(4).cast::<u32>().read_unaligned()) };
+            if flags & (LOCAL_APIC_ENABLED | LOCAL_APIC_ONLINE_CAPABLE) != 0 {
+                ids.push(apic_id);
+            }
+        }
+        offset += len as u64;
+    }
+    ids
+}
+
+/// Makes every AP, including those started later, leave its idle loop and
+/// call `main`.
+///
+/// Panics if called twice.
+pub fn run_on_aps(main: fn() -> !) {
+    AP_MAIN
+        .try_init_once(|| main)
+        .expect("APs already given a main function");
+    for cpu in 1..online_cpus() {
+        wake_up(cpu);
+    }
+}
+
+/// Sends the wakeup IPI to CPU `cpu`, which brings it out of `hlt`.
+pub fn wake_up(cpu: usize) {
+    if let (Some(lapic), Some(apic_id)) = (lapic::get(), apic_id(cpu)) {
+        lapic.send_fixed(apic_id, lapic::WAKEUP_VECTOR);
+    }
+}
+
+/// Starts all application processors listed in the MADT.
+pub fn init(mapper: &mut impl Mapper<Size4KiB>, frame_allocator: &mut BootInfoFrameAllocator) {
+    let Some(lapic) = lapic::init(mapper, frame_allocator) else {
+        println!("smp: cannot map the LAPIC, running on one CPU");
+        return;
+    };
+    let bsp = lapic.id();
+    APIC_IDS[0].store(bsp, Ordering::Relaxed);
+
+    let aps: Vec<u8> = present_apic_ids()
+        .into_iter()
+        .filter(|&id| id != bsp)
+        .collect();
+    if aps.is_empty() {
+        return;
+    }
+    let trampoline = frame_allocator
+        .allocate_low_frame()
+        .ok_or("no free page below 1 MiB")
+        .and_then(|frame| {
+            Trampoline::install(frame, ap_main, mapper, frame_allocator)
+                .map_err(|_| "cannot map the trampoline")
+        });
+    let trampoline = match trampoline {
+        Ok(trampoline) => trampoline,
+        Err(err) => {
+            println!("smp: {}, running on one CPU", err);
+            return;
+        }
+    };
+
+    for apic_id in aps {
+        let cpu = online_cpus();
+        if cpu == MAX_CPUS {
+            break;
+        }
+        if !start_ap(lapic, &trampoline, apic_id, cpu) {
+            println!("smp: CPU with APIC ID {} did not start", apic_id);
+        }
+    }
+}
+
+/// Starts one AP as CPU `cpu` and waits until it is up.
+fn start_ap(lapic: &LocalApic, trampoline: &Trampoline, apic_id: u8, cpu: usize) -> bool {
+    let stack = Box::leak(vec![0u8; AP_STACK_SIZE].into_boxed_slice());
+    let stack_top = VirtAddr::from_ptr(stack.as_ptr_range().end).align_down(16u64);
+    trampoline.prepare(stack_top, cpu as u64);
+    APIC_IDS[cpu].store(apic_id, Ordering::Relaxed);
+    STARTED.store(false, Ordering::Release);
+
+    lapic.send_init(apic_id);
+    time::delay(Duration::from_millis(10));
+    // the second SIPI is only needed if the first one got lost
+    for _ in 0..2 {
+        lapic.send_startup(apic_id, trampoline.vector());
+        if wait_started(Duration::from_millis(1)) {
+            break;
+        }
+    }
+    if !wait_started(Duration::from_millis(100)) {
+        return false;
+    }
+    ONLINE.fetch_add(1, Ordering::AcqRel);
+    true
+}
+
+fn wait_started(timeout: Duration) -> bool {
+    let deadline = time::Instant::now() + timeout;
+    while time::Instant::now() < deadline {
+        if STARTED.load(Ordering::Acquire) {
+            return true;
+        }
+        core::hint::spin_loop();
+    }
+    STARTED.load(Ordering::Acquire)
+}
+
+/// Entry point of the APs, called by the trampoline on the AP's stack.
+extern "C" fn ap_main(cpu: u64) -> ! {
+    percpu::init(cpu as usize);
+    gdt::init_ap();
+    interrupts::init_idt();
+    if let Some(lapic) = lapic::get() {
+        lapic.enable();
+    }
+    STARTED.store(true, Ordering::Release);
+    loop {
+        // checked with interrupts disabled, so the wakeup IPI of
+        // `run_on_aps` cannot slip in before the `hlt`
+        x86_64::instructions::interrupts::disable();
+        if let Ok(main) = AP_MAIN.try_get() {
+            x86_64::instructions::interrupts::enable();
+            main();
+        }
+        x86_64::instructions::interrupts::enable_and_hlt();
+    }
+}
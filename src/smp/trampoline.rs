@@ -0,0 +1,229 @@
+//! Real mode entry code of the application processors.
+//!
+//! A startup IPI starts the AP in real mode at `page * 0x1000` with
+//! `CS = page << 8`. The trampoline is copied there, loads its own GDT,
+//! enters protected mode and then long mode using the kernel's page table,
+//! and finally calls the entry function on the stack prepared by the BSP.
+//!
+//! The code only uses offsets from `ap_trampoline_start`; the absolute
+//! addresses it needs are patched into its data area before each start.
+//! The page must be identity mapped, as the AP enables paging while running
+//! from it.
+
+use crate::memory;
+use core::arch::global_asm;
+use x86_64::registers::control::Cr3;
+use x86_64::registers::model_specific::{Efer, EferFlags};
+use x86_64::structures::paging::{
+    FrameAllocator, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB, mapper::MapToError,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+global_asm!(
+    r#"
+    .section .text.ap_trampoline, "ax"
+    .global ap_trampoline_start
+    .global ap_trampoline_end
+    .global ap_trampoline_gdtr
+    .global ap_trampoline_protected
+    .global ap_trampoline_long
+    .global ap_trampoline_gdt
+    .global ap_trampoline_protected_ptr
+    .global ap_trampoline_long_ptr
+    .global ap_trampoline_cr3
+    .global ap_trampoline_efer
+    .global ap_trampoline_stack
+    .global ap_trampoline_entry
+    .global ap_trampoline_arg
+
+    .code16
+ap_trampoline_start:
+    cli
+    cld
+    movw %cs, %ax
+    movw %ax, %ds
+    # linear base of the trampoline, survives the mode switches in ebx
+    xorl %ebx, %ebx
+    movw %ax, %bx
+    shll $4, %ebx
+    lgdtl (ap_trampoline_gdtr - ap_trampoline_start)
+    movl %cr0, %eax
+    orl $1, %eax
+    movl %eax, %cr0
+    ljmpl *(ap_trampoline_protected_ptr - ap_trampoline_start)
+
+    .code32
+ap_trampoline_protected:
+    movw $0x10, %ax
+    movw %ax, %ds
+    movw %ax, %es
+    movw %ax, %ss
+    # PAE
+    movl %cr4, %eax
+    orl $(1 << 5), %eax
+    movl %eax, %cr4
+    movl (ap_trampoline_cr3 - ap_trampoline_start)(%ebx), %eax
+    movl %eax, %cr3
+    movl $0xC0000080, %ecx
+    rdmsr
+    orl (ap_trampoline_efer - ap_trampoline_start)(%ebx), %eax
+    wrmsr
+    # paging, write protect
+    movl %cr0, %eax
+    orl $0x80010000, %eax
+    movl %eax, %cr0
+    ljmpl *(ap_trampoline_long_ptr - ap_trampoline_start)(%ebx)
+
+    .code64
+ap_trampoline_long:
+    xorl %eax, %eax
+    movw %ax, %ds
+    movw %ax, %es
+    movw %ax, %ss
+    movw %ax, %fs
+    movw %ax, %gs
+    movl %ebx, %ebx
+    movq (ap_trampoline_stack - ap_trampoline_start)(%rbx), %rsp
+    movq (ap_trampoline_arg - ap_trampoline_start)(%rbx), %rdi
+    movq (ap_trampoline_entry - ap_trampoline_start)(%rbx), %rax
+    xorl %ebp, %ebp
+    callq *%rax
+    ud2
+
+    .balign 16
+ap_trampoline_gdt:
+    .quad 0
+    .quad 0x00CF9A000000FFFF
+    .quad 0x00CF92000000FFFF
+    .quad 0x00AF9A000000FFFF
+ap_trampoline_gdtr:
+    .word 4 * 8 - 1
+    .long 0
+    .balign 8
+ap_trampoline_protected_ptr:
+    .long 0
+    .word 0x08
+    .balign 8
+ap_trampoline_long_ptr:
+    .long 0
+    .word 0x18
+    .balign 8
+ap_trampoline_cr3:
+    .quad 0
+ap_trampoline_efer:
+    .quad 0
+ap_trampoline_stack:
+    .quad 0
+ap_trampoline_entry:
+    .quad 0
+ap_trampoline_arg:
+    .quad 0
+ap_trampoline_end:
+    .text
+    "#,
+    options(att_syntax)
+);
+
+unsafe extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_end: u8;
+    static ap_trampoline_gdtr: u8;
+    static ap_trampoline_protected: u8;
+    static ap_trampoline_long: u8;
+    static ap_trampoline_gdt: u8;
+    static ap_trampoline_protected_ptr: u8;
+    static ap_trampoline_long_ptr: u8;
+    static ap_trampoline_cr3: u8;
+    static ap_trampoline_efer: u8;
+    static ap_trampoline_stack: u8;
+    static ap_trampoline_entry: u8;
+    static ap_trampoline_arg: u8;
+}
+
+/// The trampoline installed in a low memory page.
+pub(super) struct Trampoline {
+    frame: PhysFrame,
+}
+
+impl Trampoline {
+    /// Copies the trampoline to `frame`, identity maps it and patches in the
+    /// current page table, `EFER` and `entry`.
+    pub(super) fn install(
+        frame: PhysFrame,
+        entry: extern "C" fn(u64) -> !,
+        mapper: &mut impl Mapper<Size4KiB>,
+        frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    ) -> Result<Trampoline, MapToError<Size4KiB>> {
+        let base = frame.start_address().as_u64();
+        assert!(base < 0x10_0000, "trampoline must be below 1 MiB");
+
+        let page = Page::<Size4KiB>::containing_address(VirtAddr::new(base));
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+            Ok(flush) => flush.flush(),
+            Err(MapToError::PageAlreadyMapped(mapped)) if mapped == frame => {}
+            Err(err) => return Err(err),
+        }
+
+        let trampoline = Trampoline { frame };
+        let len = trampoline.offset(&raw const ap_trampoline_end) as usize;
+        assert!(len <= 4096, "trampoline does not fit in a page");
+        unsafe {
+            core::ptr::copy_nonoverlapping(&raw const ap_trampoline_start, trampoline.ptr(0), len);
+        }
+
+        let (level_4_table, _) = Cr3::read();
+        let cr3 = level_4_table.start_address().as_u64();
+        assert!(
+            cr3 < 1 << 32,
+            "page table must be below 4 GiB for the trampoline"
+        );
+        let efer = Efer::read() & (EferFlags::LONG_MODE_ENABLE | EferFlags::NO_EXECUTE_ENABLE);
+
+        let gdt = base + trampoline.offset(&raw const ap_trampoline_gdt);
+        let protected = base + trampoline.offset(&raw const ap_trampoline_protected);
+        let long = base + trampoline.offset(&raw const ap_trampoline_long);
+        unsafe {
+            // the limit stays, the base follows it
+            trampoline.write(&raw const ap_trampoline_gdtr, 2, gdt as u32);
+            trampoline.write(&raw const ap_trampoline_protected_ptr, 0, protected as u32);
+            trampoline.write(&raw const ap_trampoline_long_ptr, 0, long as u32);
+            trampoline.write(&raw const ap_trampoline_cr3, 0, cr3);
+            trampoline.write(&raw const ap_trampoline_efer, 0, efer.bits());
+            trampoline.write(&raw const ap_trampoline_entry, 0, entry as *const () as u64);
+        }
+        Ok(trampoline)
+    }
+
+    /// Sets the stack and the argument passed to the entry function for the
+    /// next AP to start.
+    pub(super) fn prepare(&self, stack_top: VirtAddr, arg: u64) {
+        unsafe {
+            self.write(&raw const ap_trampoline_stack, 0, stack_top.as_u64());
+            self.write(&raw const ap_trampoline_arg, 0, arg);
+        }
+    }
+
+    /// Page number passed in the startup IPI.
+    pub(super) fn vector(&self) -> u8 {
+        (self.frame.start_address().as_u64() >> 12) as u8
+    }
+
+    fn offset(&self, symbol: *const u8) -> u64 {
+        symbol as u64 - (&raw const ap_trampoline_start) as u64
+    }
+
+    fn ptr(&self, offset: u64) -> *mut u8 {
+        let addr = PhysAddr::new(self.frame.start_address().as_u64() + offset);
+        memory::phys_to_virt(addr).as_mut_ptr()
+    }
+
+    /// Writes `value` at `extra` bytes past `symbol` in the installed copy.
+    unsafe fn write<T>(&self, symbol: *const u8, extra: u64, value: T) {
+        unsafe {
+            self.ptr(self.offset(symbol) + extra)
+                .cast::<T>()
+                .write_unaligned(value)
+        };
+    }
+}
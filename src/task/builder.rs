@@ -0,0 +1,73 @@
+use super::executor::Executor;
+use super::runqueue::SendTask;
+use super::spawner::Spawner;
+use super::{JoinHandle, Priority, Task, join};
+use alloc::boxed::Box;
+use core::future::Future;
+use core::pin::Pin;
+
+/// Configures a task before spawning it.
+///
+/// ```ignore
+/// let handle = Builder::new()
+///     .name("answer")
+///     .priority(Priority::High)
+///     .spawn(&mut executor, async { 42 });
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Builder {
+    priority: Priority,
+    name: Option<&'static str>,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Builder::default()
+    }
+
+    /// Sets the scheduling class, [`Priority::Normal`] by default.
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Names the task, see [`Task::with_name`].
+    pub fn name(mut self, name: &'static str) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// Spawns `future` onto `executor`.
+    pub fn spawn<F>(self, executor: &mut Executor, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        let (future, handle) = join::joinable(future);
+        executor.spawn_task(self.task(Task::new(future)));
+        handle
+    }
+
+    /// Spawns `future` through `spawner`, see [`Spawner::spawn`].
+    pub fn spawn_on<F>(self, spawner: &Spawner, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        spawner.spawn_with(self, future)
+    }
+
+    /// Queues `future` as a migratable task on the run queues of `cpu`.
+    pub(super) fn spawn_shared(self, cpu: usize, future: Pin<Box<dyn Future<Output = ()> + Send>>) {
+        SendTask::spawn(cpu, self.priority, self.name, future);
+    }
+
+    /// Applies the configuration to `task`.
+    pub(super) fn task(self, task: Task) -> Task {
+        let task = task.with_priority(self.priority);
+        match self.name {
+            Some(name) => task.with_name(name),
+            None => task,
+        }
+    }
+}
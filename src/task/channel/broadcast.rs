@@ -0,0 +1,239 @@
+use crate::task::{coop, sync::with_locked};
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::{
+    fmt,
+    future::poll_fn,
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+use futures_util::{ready, stream::Stream, task::AtomicWaker};
+
+/// Creates a broadcast channel that retains the last `capacity` values.
+///
+/// A receiver that falls more than `capacity` values behind skips the
+/// overwritten ones and is told how many it missed.
+///
+/// # Panics
+///
+/// Panics if `capacity` is zero.
+pub fn channel<T: Clone>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "broadcast channel capacity must be non-zero");
+    let shared = Arc::new(Shared {
+        ring: spin::Mutex::new(Ring {
+            slots: (0..capacity).map(|_| None).collect(),
+            next: 0,
+            receivers: Vec::new(),
+        }),
+        senders: AtomicUsize::new(1),
+    });
+    let receiver = Receiver::new(shared.clone(), 0);
+    (Sender { shared }, receiver)
+}
+
+/// Error returned by [`Sender::send`] when there are no receivers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("no receivers")
+    }
+}
+
+/// The receiver fell behind and missed this many values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lagged(pub u64);
+
+impl fmt::Display for Lagged {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "receiver lagged by {} values", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvError {
+    /// All senders are gone and every retained value was received.
+    Closed,
+    Lagged(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Closed,
+    Lagged(u64),
+}
+
+struct Shared<T> {
+    /// Only ever locked with interrupts disabled, so senders in interrupt
+    /// handlers cannot deadlock against a task holding it.
+    ring: spin::Mutex<Ring<T>>,
+    senders: AtomicUsize,
+}
+
+struct Ring<T> {
+    slots: Box<[Option<T>]>,
+    /// Sequence number of the next value sent.
+    next: u64,
+    receivers: Vec<Arc<AtomicWaker>>,
+}
+
+/// Sending half of a [`broadcast`](self) channel.
+///
+/// [`send`](Sender::send) never waits for receivers. It locks the ring with
+/// interrupts disabled, so it may be called from interrupt handlers, see the
+/// [module docs](super).
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// Receiving half of a [`broadcast`](self) channel.
+///
+/// As a [`Stream`] it yields `Err(Lagged)` in place of missed values and
+/// ends once all senders are gone.
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    /// Sequence number of the next value to receive.
+    next: u64,
+    waker: Arc<AtomicWaker>,
+}
+
+impl<T> Sender<T> {
+    /// Sends `value` to every receiver, returning how many there are.
+    pub fn send(&self, value: T) -> Result<usize, SendError<T>> {
+        with_locked(&self.shared.ring, |ring| {
+            if ring.receivers.is_empty() {
+                return Err(SendError(value));
+            }
+            let index = ring.index(ring.next);
+            ring.slots[index] = Some(value);
+            ring.next += 1;
+            for waker in &ring.receivers {
+                waker.wake();
+            }
+            Ok(ring.receivers.len())
+        })
+    }
+
+    /// Creates a receiver that sees values sent from now on.
+    pub fn subscribe(&self) -> Receiver<T> {
+        let next = with_locked(&self.shared.ring, |ring| ring.next);
+        Receiver::new(self.shared.clone(), next)
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        with_locked(&self.shared.ring, |ring| ring.receivers.len())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            with_locked(&self.shared.ring, |ring| {
+                for waker in &ring.receivers {
+                    waker.wake();
+                }
+            });
+        }
+    }
+}
+
+impl<T> Ring<T> {
+    fn index(&self, seq: u64) -> usize {
+        (seq % self.slots.len() as u64) as usize
+    }
+}
+
+impl<T> Receiver<T> {
+    fn new(shared: Arc<Shared<T>>, next: u64) -> Self {
+        let waker = Arc::new(AtomicWaker::new());
+        with_locked(&shared.ring, |ring| ring.receivers.push(waker.clone()));
+        Receiver {
+            shared,
+            next,
+            waker,
+        }
+    }
+}
+
+impl<T: Clone> Receiver<T> {
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let shared = self.shared.clone();
+        with_locked(&shared.ring, |ring| {
+            let oldest = ring.next.saturating_sub(ring.slots.len() as u64);
+            if self.next < oldest {
+                let missed = oldest - self.next;
+                self.next = oldest;
+                return Err(TryRecvError::Lagged(missed));
+            }
+            if self.next < ring.next {
+                let value = ring.slots[ring.index(self.next)].clone();
+                self.next += 1;
+                return Ok(value.expect("retained slot is empty"));
+            }
+            if shared.senders.load(Ordering::Acquire) == 0 {
+                return Err(TryRecvError::Closed);
+            }
+            Err(TryRecvError::Empty)
+        })
+    }
+
+    /// Receives the next value.
+    pub async fn recv(&mut self) -> Result<T, RecvError> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context) -> Poll<Result<T, RecvError>> {
+        ready!(coop::poll_proceed(cx));
+        match self.try_recv() {
+            Ok(value) => return Poll::Ready(Ok(value)),
+            Err(TryRecvError::Closed) => return Poll::Ready(Err(RecvError::Closed)),
+            Err(TryRecvError::Lagged(n)) => return Poll::Ready(Err(RecvError::Lagged(n))),
+            Err(TryRecvError::Empty) => {}
+        }
+        self.waker.register(cx.waker());
+        match self.try_recv() {
+            Ok(value) => Poll::Ready(Ok(value)),
+            Err(TryRecvError::Closed) => Poll::Ready(Err(RecvError::Closed)),
+            Err(TryRecvError::Lagged(n)) => Poll::Ready(Err(RecvError::Lagged(n))),
+            Err(TryRecvError::Empty) => Poll::Pending,
+        }
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    /// The clone starts at the same position as `self`.
+    fn clone(&self) -> Self {
+        Receiver::new(self.shared.clone(), self.next)
+    }
+}
+
+impl<T: Clone> Stream for Receiver<T> {
+    type Item = Result<T, Lagged>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        match ready!(self.poll_recv(cx)) {
+            Ok(value) => Poll::Ready(Some(Ok(value))),
+            Err(RecvError::Lagged(n)) => Poll::Ready(Some(Err(Lagged(n)))),
+            Err(RecvError::Closed) => Poll::Ready(None),
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        with_locked(&self.shared.ring, |ring| {
+            ring.receivers.retain(|waker| !Arc::ptr_eq(waker, &self.waker))
+        });
+    }
+}
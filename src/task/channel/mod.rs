@@ -0,0 +1,17 @@
+//! Async channels.
+//!
+//! - [`oneshot`]: a single value from one producer to one consumer.
+//! - [`mpsc`]: bounded multi-producer, single-consumer queue with
+//!   backpressure.
+//! - [`broadcast`]: every receiver sees every value; slow receivers lag.
+//!
+//! The non-blocking send operations of all three channels can be used to hand
+//! data from an IRQ handler to a task. Waking the receiving task may allocate
+//! when a run queue grows, and a broadcast send locks the channel's ring, but
+//! the heap allocator, the ring and the run queues are only locked or pushed
+//! to with interrupts disabled, so a handler never waits for code it
+//! interrupted.
+
+pub mod broadcast;
+pub mod mpsc;
+pub mod oneshot;
@@ -0,0 +1,157 @@
+use alloc::sync::Arc;
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU8, Ordering},
+    task::{Context, Poll},
+};
+use futures_util::task::AtomicWaker;
+
+const EMPTY: u8 = 0;
+const SENT: u8 = 1;
+/// The sender was dropped without sending.
+const SENDER_DROPPED: u8 = 2;
+/// The receiver was dropped; a sent value, if any, has been dropped with it.
+const RECEIVER_DROPPED: u8 = 3;
+/// The receiver took the value.
+const TAKEN: u8 = 4;
+
+/// Creates a channel for a single value.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let inner = Arc::new(Inner {
+        state: AtomicU8::new(EMPTY),
+        value: UnsafeCell::new(None),
+        waker: AtomicWaker::new(),
+    });
+    (
+        Sender {
+            inner: inner.clone(),
+        },
+        Receiver { inner },
+    )
+}
+
+/// Error returned when the sender was dropped without sending.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("sender dropped without sending")
+    }
+}
+
+/// Error returned by [`Receiver::try_recv`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Closed,
+}
+
+struct Inner<T> {
+    state: AtomicU8,
+    /// Written by the sender before `SENT` is published, read by the
+    /// receiver only after observing `SENT`.
+    value: UnsafeCell<Option<T>>,
+    waker: AtomicWaker,
+}
+
+unsafe impl<T: Send> Send for Inner<T> {}
+unsafe impl<T: Send> Sync for Inner<T> {}
+
+/// Sending half of a [`oneshot`](self) channel.
+pub struct Sender<T> {
+    inner: Arc<Inner<T>>,
+}
+
+/// Receiving half of a [`oneshot`](self) channel, a future resolving to the
+/// sent value.
+pub struct Receiver<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Sends `value`, handing it back if the receiver is gone.
+    ///
+    /// Never blocks; safe to call from interrupt handlers.
+    pub fn send(self, value: T) -> Result<(), T> {
+        unsafe { *self.inner.value.get() = Some(value) };
+        let result = self.inner.state.compare_exchange(
+            EMPTY,
+            SENT,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        );
+        match result {
+            Ok(_) => {
+                self.inner.waker.wake();
+                Ok(())
+            }
+            Err(_) => Err(unsafe { (*self.inner.value.get()).take() }.unwrap()),
+        }
+        // `Drop` sees a final state and does nothing
+    }
+
+    /// Returns `true` if the receiver was dropped.
+    pub fn is_closed(&self) -> bool {
+        self.inner.state.load(Ordering::Acquire) == RECEIVER_DROPPED
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let dropped = self.inner.state.compare_exchange(
+            EMPTY,
+            SENDER_DROPPED,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        );
+        if dropped.is_ok() {
+            self.inner.waker.wake();
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Takes the value if it was sent.
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        match self.inner.state.load(Ordering::Acquire) {
+            SENT => {
+                self.inner.state.store(TAKEN, Ordering::Relaxed);
+                Ok(unsafe { (*self.inner.value.get()).take() }.unwrap())
+            }
+            EMPTY => Err(TryRecvError::Empty),
+            _ => Err(TryRecvError::Closed),
+        }
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        match self.try_recv() {
+            Ok(value) => return Poll::Ready(Ok(value)),
+            Err(TryRecvError::Closed) => return Poll::Ready(Err(RecvError)),
+            Err(TryRecvError::Empty) => {}
+        }
+        self.inner.waker.register(cx.waker());
+        match self.try_recv() {
+            Ok(value) => Poll::Ready(Ok(value)),
+            Err(TryRecvError::Closed) => Poll::Ready(Err(RecvError)),
+            Err(TryRecvError::Empty) => Poll::Pending,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let previous = self.inner.state.swap(RECEIVER_DROPPED, Ordering::AcqRel);
+        if previous == SENT {
+            // drop the value that nobody will receive
+            unsafe { (*self.inner.value.get()).take() };
+        }
+    }
+}
@@ -0,0 +1,71 @@
+//! Cooperative scheduling helpers.
+//!
+//! Each poll of a task gets a budget of [`BUDGET`] units. Futures that can be
+//! ready over and over (streams fed by interrupts, channels) spend one unit
+//! per item with [`poll_proceed`]; once the budget is used up they return
+//! `Pending` and reschedule the task, so a busy task cannot monopolize the
+//! executor.
+
+use crate::cpu_local;
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU32, Ordering},
+    task::{Context, Poll},
+};
+
+/// Units available to a task per poll.
+pub const BUDGET: u32 = 128;
+
+cpu_local! {
+    /// Remaining budget of the task being polled on the CPU. Outside of an
+    /// executor poll the budget is unlimited.
+    static REMAINING: AtomicU32 = AtomicU32::new(u32::MAX);
+}
+
+/// Runs `f` (a task poll) with a fresh budget.
+pub(super) fn with_budget<R>(f: impl FnOnce() -> R) -> R {
+    let previous = REMAINING.get().swap(BUDGET, Ordering::Relaxed);
+    let result = f();
+    REMAINING.get().store(previous, Ordering::Relaxed);
+    result
+}
+
+/// Spends one unit of the current task's budget.
+///
+/// Returns `Pending` and wakes the task if the budget is exhausted; the
+/// caller must then return `Pending` as well.
+pub fn poll_proceed(cx: &mut Context) -> Poll<()> {
+    let remaining = REMAINING.get().load(Ordering::Relaxed);
+    if remaining == 0 {
+        cx.waker().wake_by_ref();
+        return Poll::Pending;
+    }
+    if remaining != u32::MAX {
+        REMAINING.get().store(remaining - 1, Ordering::Relaxed);
+    }
+    Poll::Ready(())
+}
+
+/// Yields to other ready tasks once.
+pub fn yield_now() -> YieldNow {
+    YieldNow { yielded: false }
+}
+
+/// Future returned by [`yield_now`].
+pub struct YieldNow {
+    yielded: bool,
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.yielded {
+            return Poll::Ready(());
+        }
+        self.yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
@@ -1,29 +1,76 @@
-use super::{Task, TaskId};
-use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
+use super::runqueue::{self, SendTask};
+use super::spawner::Spawner;
+use super::{Builder, JoinHandle, Priority, Task, TaskId, coop, watchdog};
+use crate::percpu;
+use crate::time::Instant;
+use alloc::{collections::BTreeMap, sync::Arc, task::Wake, vec::Vec};
+use core::fmt;
+use core::future::Future;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use core::task::{Context, Poll, Waker};
-use crossbeam_queue::ArrayQueue;
+use core::time::Duration;
+use crossbeam_queue::SegQueue;
 
+/// Async executor of one CPU.
+///
+/// Tasks spawned directly onto the executor need not be `Send` and are only
+/// ever polled by it. Tasks spawned through a [`Spawner`] go to the shared
+/// per-CPU run queues of [`runqueue`]: the executor serves its own CPU's
+/// queues and steals from other CPUs when it runs out of work, so these
+/// tasks migrate to whichever CPU has time for them.
+///
+/// Ready tasks wait in one queue per [`Priority`]; the highest non-empty
+/// queue is always served first, in FIFO order. Busy tasks are kept in check
+/// by the per-poll budget of [`coop`].
+///
+/// An executor belongs to the CPU that created it.
 pub struct Executor {
+    cpu: usize,
     tasks: BTreeMap<TaskId, Task>,
-    task_queue: Arc<ArrayQueue<TaskId>>,
-    waker_cache: BTreeMap<TaskId, Waker>,
+    run_queues: [Arc<SegQueue<TaskId>>; Priority::COUNT],
+    waker_cache: BTreeMap<TaskId, Arc<TaskWaker>>,
+}
+
+/// A task picked to be polled next.
+enum Ready {
+    Local(TaskId),
+    Shared(Arc<SendTask>),
 }
 
 impl Executor {
     pub fn new() -> Self {
         Executor {
+            cpu: percpu::current_cpu(),
             tasks: BTreeMap::new(),
-            task_queue: Arc::new(ArrayQueue::new(100)),
+            run_queues: core::array::from_fn(|_| Arc::new(SegQueue::new())),
             waker_cache: BTreeMap::new(),
         }
     }
 
-    pub fn spawn(&mut self, task: Task) {
+    /// Returns a handle that spawns tasks onto the run queues of this
+    /// executor's CPU.
+    pub fn spawner(&self) -> Spawner {
+        Spawner::new(self.cpu)
+    }
+
+    /// Spawns `future` as a new task and returns a handle to its output.
+    pub fn spawn<F>(&mut self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        Builder::new().spawn(self, future)
+    }
+
+    pub fn spawn_task(&mut self, task: Task) {
         let task_id = task.id;
+        let run_queue = self.run_queues[task.priority.index()].clone();
         if self.tasks.insert(task.id, task).is_some() {
             panic!("task with same ID already in tasks");
         }
-        self.task_queue.push(task_id).expect("queue full");
+        let waker = Arc::new(TaskWaker::new(task_id, self.cpu, run_queue));
+        waker.wake_task();
+        self.waker_cache.insert(task_id, waker);
     }
 
     pub fn run(&mut self) -> ! {
@@ -34,64 +81,240 @@ impl Executor {
     }
 
     fn run_ready_tasks(&mut self) {
-        // destructure `self` to avoid borrow checker errors
-        let Self {
-            tasks,
-            task_queue,
-            waker_cache,
-        } = self;
-
-        while let Some(task_id) = task_queue.pop() {
-            let task = match tasks.get_mut(&task_id) {
-                Some(task) => task,
-                None => continue, // task no longer exists
-            };
-            let waker = waker_cache
-                .entry(task_id)
-                .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
-            let mut context = Context::from_waker(waker);
-            match task.poll(&mut context) {
-                Poll::Ready(()) => {
-                    // task done -> remove it and its cached waker
-                    tasks.remove(&task_id);
-                    waker_cache.remove(&task_id);
-                }
-                Poll::Pending => {}
+        while let Some(ready) = self.next_ready() {
+            match ready {
+                Ready::Local(task_id) => self.poll_task(task_id),
+                Ready::Shared(task) => task.poll(),
             }
         }
     }
 
+    /// Pops a ready task from the highest priority queue that has one,
+    /// preferring this executor's own tasks, then steals one if there is
+    /// none.
+    fn next_ready(&self) -> Option<Ready> {
+        Priority::ALL
+            .into_iter()
+            .find_map(|priority| {
+                self.run_queues[priority.index()]
+                    .pop()
+                    .map(Ready::Local)
+                    .or_else(|| runqueue::pop(priority).map(Ready::Shared))
+            })
+            .or_else(|| runqueue::steal().map(Ready::Shared))
+    }
+
+    fn has_ready(&self) -> bool {
+        self.run_queues.iter().any(|queue| !queue.is_empty()) || runqueue::has_work()
+    }
+
+    fn poll_task(&mut self, task_id: TaskId) {
+        let (task, task_waker) = match (
+            self.tasks.get_mut(&task_id),
+            self.waker_cache.get(&task_id),
+        ) {
+            (Some(task), Some(task_waker)) => (task, task_waker),
+            _ => return, // task no longer exists
+        };
+        // clear the flag first, so that wakes during the poll queue the task again
+        task_waker.scheduled.store(false, Ordering::Release);
+        let waker = Waker::from(task_waker.clone());
+        let mut context = Context::from_waker(&waker);
+        let start = Instant::now();
+        watchdog::poll_started(task_id, task.name);
+        let poll = coop::with_budget(|| task.poll(&mut context));
+        watchdog::poll_finished();
+        task.polls += 1;
+        task.poll_time += start.elapsed();
+        match poll {
+            Poll::Ready(()) => {
+                // task done -> remove it and its cached waker
+                self.tasks.remove(&task_id);
+                self.waker_cache.remove(&task_id);
+            }
+            Poll::Pending => {}
+        }
+    }
+
     pub fn run_ready_once(&mut self) {
         self.run_ready_tasks();
     }
 
+    /// Runs the executor until `future` completes and returns its output.
+    ///
+    /// Other spawned tasks make progress in the meantime. The task driving
+    /// `future` cannot be aborted, since its handle never leaves this call.
+    pub fn block_on<F>(&mut self, future: F) -> F::Output
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        let handle = self.spawn(future);
+        loop {
+            self.run_ready_tasks();
+            if let Some(output) = handle.take_output() {
+                return output.expect("block_on task cannot be cancelled");
+            }
+            self.sleep_if_idle();
+        }
+    }
+
+    /// Snapshot of every live task of this executor and of every
+    /// [`Spawner`] task last polled on its CPU, ordered by ID.
+    pub fn tasks(&self) -> Vec<TaskInfo> {
+        let mut tasks: Vec<TaskInfo> = self
+            .tasks
+            .values()
+            .map(|task| {
+                let waker = &self.waker_cache[&task.id];
+                TaskInfo {
+                    id: task.id,
+                    name: task.name,
+                    priority: task.priority,
+                    state: if waker.scheduled.load(Ordering::Acquire) {
+                        TaskState::Ready
+                    } else {
+                        TaskState::Pending
+                    },
+                    polls: task.polls,
+                    poll_time: task.poll_time,
+                    last_woken: waker.last_woken(),
+                }
+            })
+            .collect();
+        tasks.extend(runqueue::tasks_of(self.cpu).iter().map(|task| TaskInfo {
+            id: task.id,
+            name: task.name,
+            priority: task.priority,
+            state: if task.scheduled.load(Ordering::Acquire) {
+                TaskState::Ready
+            } else {
+                TaskState::Pending
+            },
+            polls: task.polls.load(Ordering::Relaxed),
+            poll_time: task.poll_time(),
+            last_woken: Instant::from_nanos(task.last_woken.load(Ordering::Relaxed)),
+        }));
+        tasks.sort_by_key(|info| info.id);
+        tasks
+    }
+
+    /// Prints [`tasks`](Executor::tasks) as a table, one task per line.
+    pub fn dump_tasks(&self) {
+        crate::println!(
+            "{:>6} {:<7} {:<10} {:>8} {:>12} {:>12}  name",
+            "id",
+            "state",
+            "priority",
+            "polls",
+            "poll time",
+            "woken"
+        );
+        for info in self.tasks() {
+            crate::println!("{}", info);
+        }
+    }
+
+    /// Lets other threads run, or halts, until an interrupt, another thread
+    /// or another CPU might have woken a task.
+    ///
+    /// The CPU is marked idle before the final check for work, so a task
+    /// queued after the check comes with a wakeup IPI, which stays pending
+    /// until the `hlt`.
     fn sleep_if_idle(&self) {
-        use x86_64::instructions::interrupts::{self, enable_and_hlt};
+        use x86_64::instructions::interrupts;
 
         interrupts::disable();
-        if self.task_queue.is_empty() {
-            enable_and_hlt();
-        } else {
+        runqueue::set_idle(true);
+        if self.has_ready() {
             interrupts::enable();
+        } else {
+            crate::thread::halt();
         }
+        runqueue::set_idle(false);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    /// Woken and waiting in a run queue.
+    Ready,
+    /// Waiting for a wake.
+    Pending,
+}
+
+/// Statistics of a live task, see [`Executor::tasks`].
+#[derive(Debug, Clone)]
+pub struct TaskInfo {
+    pub id: TaskId,
+    pub name: Option<&'static str>,
+    pub priority: Priority,
+    pub state: TaskState,
+    pub polls: u64,
+    /// Time spent inside `poll`, summed over all polls.
+    pub poll_time: Duration,
+    pub last_woken: Instant,
+}
+
+impl fmt::Display for TaskInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self.state {
+            TaskState::Ready => "ready",
+            TaskState::Pending => "pending",
+        };
+        write!(
+            f,
+            "{:>6} {:<7} {:<10} {:>8} {:>9}.{:02}ms {:>9}.{:02}ms  {}",
+            self.id.0,
+            state,
+            alloc::format!("{:?}", self.priority),
+            self.polls,
+            self.poll_time.as_millis(),
+            self.poll_time.as_micros() % 1000 / 10,
+            self.last_woken.as_nanos() / 1_000_000,
+            self.last_woken.as_nanos() / 10_000 % 100,
+            self.name.unwrap_or("-"),
+        )
     }
 }
 
+/// Waker of a single task, pushes it to the run queue of its priority.
+///
+/// The `scheduled` flag deduplicates wakes: a task is pushed to the run queue
+/// at most once until it is polled, so the queue never holds more entries
+/// than there are tasks and waking never fails.
 struct TaskWaker {
     task_id: TaskId,
-    task_queue: Arc<ArrayQueue<TaskId>>,
+    /// CPU of the executor, woken if it halted.
+    cpu: usize,
+    scheduled: AtomicBool,
+    /// [`Instant`] of the last wake in nanoseconds.
+    last_woken: AtomicU64,
+    run_queue: Arc<SegQueue<TaskId>>,
 }
 
 impl TaskWaker {
-    fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
-        Waker::from(Arc::new(TaskWaker {
+    fn new(task_id: TaskId, cpu: usize, run_queue: Arc<SegQueue<TaskId>>) -> TaskWaker {
+        TaskWaker {
             task_id,
-            task_queue,
-        }))
+            cpu,
+            scheduled: AtomicBool::new(false),
+            last_woken: AtomicU64::new(0),
+            run_queue,
+        }
+    }
+
+    fn last_woken(&self) -> Instant {
+        Instant::from_nanos(self.last_woken.load(Ordering::Relaxed))
     }
 
     fn wake_task(&self) {
-        self.task_queue.push(self.task_id).expect("task_queue full");
+        self.last_woken
+            .store(Instant::now().as_nanos(), Ordering::Relaxed);
+        if !self.scheduled.swap(true, Ordering::AcqRel) {
+            runqueue::push_irqsafe(&self.run_queue, self.task_id);
+            runqueue::wake_cpu(self.cpu);
+        }
     }
 }
 
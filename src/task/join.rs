@@ -0,0 +1,174 @@
+use alloc::sync::Arc;
+use core::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll},
+};
+use futures_util::task::AtomicWaker;
+use spin::Mutex;
+
+/// Error returned when joining a task that was aborted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("task was cancelled")
+    }
+}
+
+/// Handle to a spawned task, resolves to the task's output.
+///
+/// Dropping the handle detaches the task; it keeps running and its output is
+/// discarded.
+pub struct JoinHandle<T> {
+    inner: Arc<JoinInner<T>>,
+}
+
+/// Handle that can abort a task without being able to join it.
+#[derive(Clone)]
+pub struct AbortHandle {
+    state: Arc<AbortState>,
+}
+
+struct JoinInner<T> {
+    output: Mutex<Option<Result<T, Cancelled>>>,
+    finished: AtomicBool,
+    /// Woken when the task finishes, registered by the joiner.
+    join_waker: AtomicWaker,
+    abort: Arc<AbortState>,
+}
+
+struct AbortState {
+    aborted: AtomicBool,
+    /// Woken on abort, registered by the task itself.
+    task_waker: AtomicWaker,
+}
+
+impl AbortState {
+    fn abort(&self) {
+        self.aborted.store(true, Ordering::Release);
+        self.task_waker.wake();
+    }
+}
+
+impl<T> JoinInner<T> {
+    fn finish(&self, output: Result<T, Cancelled>) {
+        *self.output.lock() = Some(output);
+        self.finished.store(true, Ordering::Release);
+        self.join_waker.wake();
+    }
+}
+
+impl<T> JoinHandle<T> {
+    /// Returns a handle that can abort the task.
+    pub fn abort_handle(&self) -> AbortHandle {
+        AbortHandle {
+            state: self.inner.abort.clone(),
+        }
+    }
+
+    /// Aborts the task, see [`AbortHandle::abort`].
+    pub fn abort(&self) {
+        self.inner.abort.abort();
+    }
+
+    /// Returns `true` once the task has returned or was aborted.
+    pub fn is_finished(&self) -> bool {
+        self.inner.finished.load(Ordering::Acquire)
+    }
+
+    /// Takes the output of a finished task.
+    pub(super) fn take_output(&self) -> Option<Result<T, Cancelled>> {
+        self.inner.output.lock().take()
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, Cancelled>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if self.is_finished() {
+            return Poll::Ready(self.take_output().expect("JoinHandle polled after completion"));
+        }
+
+        self.inner.join_waker.register(cx.waker());
+        match self.is_finished() {
+            true => Poll::Ready(self.take_output().expect("JoinHandle polled after completion")),
+            false => Poll::Pending,
+        }
+    }
+}
+
+impl AbortHandle {
+    /// Aborts the task.
+    ///
+    /// The executor drops the task's future the next time it gets to it,
+    /// running its destructors, and joiners resolve to `Err(Cancelled)`.
+    /// Aborting a finished task does nothing.
+    pub fn abort(&self) {
+        self.state.abort();
+    }
+}
+
+/// Wraps a future so that its output is handed to a [`JoinHandle`] and it
+/// can be aborted.
+struct Joinable<F: Future> {
+    future: Option<F>,
+    inner: Arc<JoinInner<F::Output>>,
+}
+
+impl<F: Future> Future for Joinable<F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        // SAFETY: `future` is structurally pinned. It is never moved out of
+        // `self`, only dropped in place by overwriting it with `None`.
+        let this = unsafe { self.get_unchecked_mut() };
+
+        if this.inner.abort.aborted.load(Ordering::Acquire) {
+            this.future = None;
+            this.inner.finish(Err(Cancelled));
+            return Poll::Ready(());
+        }
+        this.inner.abort.task_waker.register(cx.waker());
+
+        let Some(future) = this.future.as_mut() else {
+            return Poll::Ready(());
+        };
+        match unsafe { Pin::new_unchecked(future) }.poll(cx) {
+            Poll::Ready(output) => {
+                this.future = None;
+                this.inner.finish(Ok(output));
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Wraps `future` so that its output is handed to the returned [`JoinHandle`].
+pub(super) fn joinable<F>(future: F) -> (impl Future<Output = ()>, JoinHandle<F::Output>)
+where
+    F: Future,
+{
+    let inner = Arc::new(JoinInner {
+        output: Mutex::new(None),
+        finished: AtomicBool::new(false),
+        join_waker: AtomicWaker::new(),
+        abort: Arc::new(AbortState {
+            aborted: AtomicBool::new(false),
+            task_waker: AtomicWaker::new(),
+        }),
+    });
+    let handle = JoinHandle {
+        inner: inner.clone(),
+    };
+    let task = Joinable {
+        future: Some(future),
+        inner,
+    };
+    (task, handle)
+}
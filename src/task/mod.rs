@@ -1,35 +1,120 @@
 use alloc::boxed::Box;
 use core::{
+    fmt,
     future::Future,
     pin::Pin,
     sync::atomic::{AtomicU64, Ordering},
     task::{Context, Poll},
+    time::Duration,
 };
 
+pub mod builder;
+pub mod channel;
+pub mod coop;
 pub mod executor;
+pub mod join;
 pub mod keyboard;
+mod runqueue;
 pub mod simple_executor;
+pub mod spawner;
+pub mod sync;
+pub mod watchdog;
+
+pub use builder::Builder;
+pub use coop::yield_now;
+pub use join::{AbortHandle, Cancelled, JoinHandle};
+pub use spawner::{Spawner, spawn};
+
+/// Scheduling class of a task.
+///
+/// The executor keeps one ready queue per class and always polls the
+/// highest class with a ready task first. Tasks within a class run in FIFO
+/// order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Priority {
+    /// Deferred interrupt work, see [`crate::interrupts::deferred`].
+    BottomHalf,
+    High,
+    #[default]
+    Normal,
+    /// Runs only when no other task is ready.
+    Idle,
+}
+
+impl Priority {
+    pub const COUNT: usize = 4;
+
+    /// Every class, highest first.
+    pub const ALL: [Priority; Priority::COUNT] = [
+        Priority::BottomHalf,
+        Priority::High,
+        Priority::Normal,
+        Priority::Idle,
+    ];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
 
 pub struct Task {
     id: TaskId,
+    priority: Priority,
+    name: Option<&'static str>,
     future: Pin<Box<dyn Future<Output = ()>>>,
+    /// Number of times the executor polled the task.
+    polls: u64,
+    /// Time spent inside `poll`, summed over all polls.
+    poll_time: Duration,
 }
 
 impl Task {
     pub fn new(future: impl Future<Output = ()> + 'static) -> Task {
+        Task::from_pinned(Box::pin(future))
+    }
+
+    fn from_pinned(future: Pin<Box<dyn Future<Output = ()>>>) -> Task {
         Task {
             id: TaskId::new(),
-            future: Box::pin(future),
+            priority: Priority::Normal,
+            name: None,
+            future,
+            polls: 0,
+            poll_time: Duration::ZERO,
         }
     }
 
+    pub fn with_priority(mut self, priority: Priority) -> Task {
+        self.priority = priority;
+        self
+    }
+
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    /// Names the task in [`dump_tasks`](executor::Executor::dump_tasks)
+    /// output.
+    pub fn with_name(mut self, name: &'static str) -> Task {
+        self.name = Some(name);
+        self
+    }
+
+    pub fn name(&self) -> Option<&'static str> {
+        self.name
+    }
+
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
     fn poll(&mut self, context: &mut Context) -> Poll<()> {
         self.future.as_mut().poll(context)
     }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-struct TaskId(u64);
+pub struct TaskId(u64);
 
 impl TaskId {
     fn new() -> Self {
@@ -37,3 +122,9 @@ impl TaskId {
         TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
     }
 }
+
+impl fmt::Display for TaskId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
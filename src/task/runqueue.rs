@@ -0,0 +1,254 @@
+//! Per-CPU run queues of tasks that may migrate between CPUs.
+//!
+//! Tasks spawned through a [`Spawner`](super::Spawner) are `Send` and live
+//! here instead of inside one executor. Every CPU has one queue per
+//! [`Priority`]; a woken task goes to the queue of the CPU that polled it
+//! last, and an executor that runs out of work steals from the other CPUs'
+//! queues. A CPU whose executor halts for lack of work is marked idle, so a
+//! wake aimed at it, or work it could steal, sends a wakeup IPI.
+
+use super::{Priority, TaskId, coop, watchdog};
+use crate::smp;
+use crate::time::Instant;
+use crate::{cpu_local, percpu};
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+    task::Wake,
+    vec::Vec,
+};
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{self, AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use core::task::{Context, Waker};
+use core::time::Duration;
+use crossbeam_queue::SegQueue;
+use x86_64::instructions::interrupts::without_interrupts;
+
+type SendFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+cpu_local! {
+    static QUEUES: [SegQueue<Arc<SendTask>>; Priority::COUNT] =
+        [const { SegQueue::new() }; Priority::COUNT];
+    /// Number of executors halted on the CPU waiting for work.
+    static IDLE: AtomicUsize = AtomicUsize::new(0);
+}
+
+/// Every live migratable task, for [`super::executor::Executor::tasks`].
+/// Only locked with interrupts disabled.
+static REGISTRY: spin::Mutex<BTreeMap<TaskId, Weak<SendTask>>> = spin::Mutex::new(BTreeMap::new());
+
+/// A task that any CPU's executor may poll.
+pub(super) struct SendTask {
+    pub(super) id: TaskId,
+    pub(super) priority: Priority,
+    pub(super) name: Option<&'static str>,
+    /// `None` once the task completed. Locked for the duration of a poll.
+    future: spin::Mutex<Option<SendFuture>>,
+    /// Set while the task sits in a run queue, deduplicates wakes.
+    pub(super) scheduled: AtomicBool,
+    /// Set when the task was popped while another poll had its future
+    /// locked, that poll queues the task again once it is done.
+    repoll: AtomicBool,
+    /// CPU whose queue the task is woken into: the one that polled it last.
+    pub(super) cpu: AtomicUsize,
+    pub(super) polls: AtomicU64,
+    pub(super) poll_nanos: AtomicU64,
+    pub(super) last_woken: AtomicU64,
+}
+
+impl SendTask {
+    /// Creates a task owned by `cpu` and queues it there.
+    pub(super) fn spawn(
+        cpu: usize,
+        priority: Priority,
+        name: Option<&'static str>,
+        future: SendFuture,
+    ) {
+        let task = Arc::new(SendTask {
+            id: TaskId::new(),
+            priority,
+            name,
+            future: spin::Mutex::new(Some(future)),
+            scheduled: AtomicBool::new(false),
+            repoll: AtomicBool::new(false),
+            cpu: AtomicUsize::new(cpu),
+            polls: AtomicU64::new(0),
+            poll_nanos: AtomicU64::new(0),
+            last_woken: AtomicU64::new(0),
+        });
+        without_interrupts(|| REGISTRY.lock().insert(task.id, Arc::downgrade(&task)));
+        task.wake_task();
+    }
+
+    fn wake_task(self: &Arc<Self>) {
+        self.last_woken
+            .store(Instant::now().as_nanos(), Ordering::Relaxed);
+        if !self.scheduled.swap(true, Ordering::AcqRel) {
+            self.enqueue();
+        }
+    }
+
+    /// Pushes the task to the queue of its CPU.
+    fn enqueue(self: &Arc<Self>) {
+        let cpu = self.cpu.load(Ordering::Acquire);
+        push_irqsafe(&QUEUES.for_cpu(cpu)[self.priority.index()], self.clone());
+        kick(cpu);
+    }
+
+    pub(super) fn poll_time(&self) -> Duration {
+        Duration::from_nanos(self.poll_nanos.load(Ordering::Relaxed))
+    }
+
+    /// Polls the task on the calling CPU, which becomes its owner.
+    pub(super) fn poll(self: Arc<Self>) {
+        let Some(mut future) = self.lock_future() else {
+            return;
+        };
+        let Some(poll_future) = future.as_mut() else {
+            return; // already completed
+        };
+        self.cpu.store(percpu::current_cpu(), Ordering::Release);
+        // clear the flag first, so that wakes during the poll queue the task again
+        self.scheduled.store(false, Ordering::Release);
+
+        let waker = Waker::from(self.clone());
+        let mut context = Context::from_waker(&waker);
+        let start = Instant::now();
+        watchdog::poll_started(self.id, self.name);
+        let poll = coop::with_budget(|| poll_future.as_mut().poll(&mut context));
+        watchdog::poll_finished();
+        self.polls.fetch_add(1, Ordering::Relaxed);
+        self.poll_nanos
+            .fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+
+        let done = poll.is_ready();
+        if done {
+            *future = None;
+            without_interrupts(|| REGISTRY.lock().remove(&self.id));
+        }
+        drop(future);
+
+        // pairs with the fence in `lock_future`
+        atomic::fence(Ordering::SeqCst);
+        if self.repoll.swap(false, Ordering::Relaxed) && !done {
+            // `scheduled` is still set by the wake that queued the task
+            self.enqueue();
+        }
+    }
+
+    /// Locks the future for a poll.
+    ///
+    /// Fails if the task was woken while another CPU or a preempted executor
+    /// thread is still polling it. Retrying right away would keep the
+    /// executor busy until that poll ends, so the task is left to the other
+    /// poll to queue again.
+    fn lock_future(&self) -> Option<spin::MutexGuard<'_, Option<SendFuture>>> {
+        if let Some(future) = self.future.try_lock() {
+            return Some(future);
+        }
+        self.repoll.store(true, Ordering::Relaxed);
+        // either the other poll sees the flag after unlocking, or the lock
+        // is free now
+        atomic::fence(Ordering::SeqCst);
+        let future = self.future.try_lock()?;
+        self.repoll.store(false, Ordering::Relaxed);
+        Some(future)
+    }
+}
+
+impl Wake for SendTask {
+    fn wake(self: Arc<Self>) {
+        self.wake_task();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wake_task();
+    }
+}
+
+/// Pushes `value` to a queue that interrupt handlers push to as well.
+///
+/// A push interrupted while the queue grows would make a push from an
+/// interrupt handler on the same CPU spin forever, so interrupts are
+/// disabled for the push.
+pub(super) fn push_irqsafe<T>(queue: &SegQueue<T>, value: T) {
+    without_interrupts(|| queue.push(value));
+}
+
+/// Pops a task of class `priority` from the calling CPU's queue.
+pub(super) fn pop(priority: Priority) -> Option<Arc<SendTask>> {
+    QUEUES.get()[priority.index()].pop()
+}
+
+/// Takes the highest priority task queued on another CPU.
+pub(super) fn steal() -> Option<Arc<SendTask>> {
+    let me = percpu::current_cpu();
+    let cpus = smp::online_cpus();
+    Priority::ALL.into_iter().find_map(|priority| {
+        (1..cpus)
+            .map(|i| (me + i) % cpus)
+            .find_map(|cpu| QUEUES.for_cpu(cpu)[priority.index()].pop())
+    })
+}
+
+/// Whether the calling CPU has queued tasks or could steal some.
+pub(super) fn has_work() -> bool {
+    (0..smp::online_cpus()).any(|cpu| QUEUES.for_cpu(cpu).iter().any(|queue| !queue.is_empty()))
+}
+
+/// Marks an executor on the calling CPU as halted or running again, see
+/// [`kick`].
+pub(super) fn set_idle(idle: bool) {
+    if idle {
+        IDLE.get().fetch_add(1, Ordering::SeqCst);
+    } else {
+        IDLE.get().fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Makes sure a task queued on `cpu` gets polled soon: wakes `cpu` if its
+/// executor halted, and if it is busy instead wakes some idle CPU to steal
+/// the task.
+fn kick(cpu: usize) {
+    // pairs with the idle executor marking itself before checking the queues
+    atomic::fence(Ordering::SeqCst);
+    let me = percpu::current_cpu();
+    if is_idle(cpu) {
+        // an idle calling CPU runs this in an interrupt handler and goes
+        // back to its executor right after
+        if cpu != me {
+            smp::wake_up(cpu);
+        }
+        return;
+    }
+    if let Some(thief) = (0..smp::online_cpus()).find(|&other| other != me && is_idle(other)) {
+        smp::wake_up(thief);
+    }
+}
+
+/// Wakes `cpu` if an executor halted on it, for tasks only that CPU can
+/// poll.
+pub(super) fn wake_cpu(cpu: usize) {
+    atomic::fence(Ordering::SeqCst);
+    if cpu != percpu::current_cpu() && is_idle(cpu) {
+        smp::wake_up(cpu);
+    }
+}
+
+fn is_idle(cpu: usize) -> bool {
+    IDLE.for_cpu(cpu).load(Ordering::SeqCst) != 0
+}
+
+/// Migratable tasks last run on `cpu`.
+pub(super) fn tasks_of(cpu: usize) -> Vec<Arc<SendTask>> {
+    without_interrupts(|| {
+        REGISTRY
+            .lock()
+            .values()
+            .filter_map(Weak::upgrade)
+            .filter(|task| task.cpu.load(Ordering::Relaxed) == cpu)
+            .collect()
+    })
+}
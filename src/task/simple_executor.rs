@@ -1,44 +1,100 @@
-use super::Task;
-use alloc::collections::VecDeque;
-use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+//! A minimal executor for a single future, for early boot and tests.
+//!
+//! [`block_on`] needs neither an [`Executor`](super::executor::Executor)
+//! nor kernel threads: it polls the future whenever its waker fires and
+//! halts the CPU in between.
 
-pub struct SimpleExecutor {
-    task_queue: VecDeque<Task>,
-}
+use super::coop;
+use crate::{percpu, smp};
+use alloc::{sync::Arc, task::Wake};
+use core::future::Future;
+use core::pin::pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+use x86_64::instructions::interrupts;
 
-impl SimpleExecutor {
-    pub fn new() -> SimpleExecutor {
-        SimpleExecutor {
-            task_queue: VecDeque::new(),
+/// Polls `future` on the calling CPU until it completes and returns its
+/// output.
+///
+/// While the future is pending the CPU runs other threads or halts, with
+/// interrupts enabled, until the future's waker is called. Wakes from other
+/// CPUs send a wakeup IPI.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let signal = Arc::new(Signal {
+        cpu: percpu::current_cpu(),
+        woken: AtomicBool::new(true),
+        halted: AtomicBool::new(false),
+    });
+    let waker = Waker::from(signal.clone());
+    let mut context = Context::from_waker(&waker);
+    loop {
+        if signal.woken.swap(false, Ordering::SeqCst) {
+            if let Poll::Ready(output) = coop::with_budget(|| future.as_mut().poll(&mut context)) {
+                return output;
+            }
+            continue;
+        }
+        // marked halted before the final check, so a wake after the check
+        // comes with an IPI, which stays pending until the `hlt`
+        interrupts::disable();
+        signal.halted.store(true, Ordering::SeqCst);
+        if signal.woken.load(Ordering::SeqCst) {
+            interrupts::enable();
+        } else {
+            crate::thread::halt();
         }
+        signal.halted.store(false, Ordering::SeqCst);
     }
+}
+
+/// Waker of [`block_on`].
+struct Signal {
+    cpu: usize,
+    woken: AtomicBool,
+    /// Set while `block_on` may be halted.
+    halted: AtomicBool,
+}
 
-    pub fn spawn(&mut self, task: Task) {
-        self.task_queue.push_back(task)
+impl Wake for Signal {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
     }
 
-    pub fn run(&mut self) {
-        while let Some(mut task) = self.task_queue.pop_front() {
-            let waker = dummy_waker();
-            let mut context = Context::from_waker(&waker);
-            match task.poll(&mut context) {
-                Poll::Ready(()) => {} // task done
-                Poll::Pending => self.task_queue.push_back(task),
-            }
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::SeqCst);
+        // a wake on the same CPU comes from an interrupt handler or another
+        // thread, both of which end the halt
+        if self.halted.load(Ordering::SeqCst) && self.cpu != percpu::current_cpu() {
+            smp::wake_up(self.cpu);
         }
     }
 }
 
-fn dummy_raw_waker() -> RawWaker {
-    fn no_op(_: *const ()) {}
-    fn clone(_: *const ()) -> RawWaker {
-        dummy_raw_waker()
-    }
+#[test_case]
+fn test_block_on_returns_output() {
+    assert_eq!(block_on(async { 42 }), 42);
+}
 
-    let vtable = &RawWakerVTable::new(clone, no_op, no_op, no_op);
-    RawWaker::new(0 as *const (), vtable)
+#[test_case]
+fn test_block_on_yield() {
+    let output = block_on(async {
+        super::yield_now().await;
+        super::yield_now().await;
+        "done"
+    });
+    assert_eq!(output, "done");
 }
 
-fn dummy_waker() -> Waker {
-    unsafe { Waker::from_raw(dummy_raw_waker()) }
+#[test_case]
+fn test_block_on_woken_by_thread() {
+    use super::channel::oneshot;
+
+    let (tx, rx) = oneshot::channel();
+    let thread = crate::thread::spawn(move || {
+        crate::thread::sleep(core::time::Duration::from_millis(20));
+        tx.send(7).unwrap();
+    });
+    assert_eq!(block_on(rx), Ok(7));
+    thread.join();
 }
@@ -0,0 +1,68 @@
+use super::{Builder, JoinHandle, join};
+use alloc::boxed::Box;
+use conquer_once::spin::OnceCell;
+use core::future::Future;
+
+static GLOBAL: OnceCell<Spawner> = OnceCell::uninit();
+
+/// Cloneable handle that spawns `Send` tasks onto the run queues of an
+/// [`Executor`]'s CPU without borrowing the executor.
+///
+/// The heap allocator and the run queues are only locked or pushed to with
+/// interrupts disabled, so a `Spawner` can be used from running tasks, other
+/// CPUs and interrupt handlers alike. Executors on other CPUs may steal the
+/// tasks.
+///
+/// [`Executor`]: super::executor::Executor
+#[derive(Clone)]
+pub struct Spawner {
+    cpu: usize,
+}
+
+impl Spawner {
+    pub(super) fn new(cpu: usize) -> Self {
+        Spawner { cpu }
+    }
+
+    /// Spawns `future` as a new task and returns a handle to its output.
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.spawn_with(Builder::new(), future)
+    }
+
+    pub(super) fn spawn_with<F>(&self, builder: Builder, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let (future, handle) = join::joinable(future);
+        builder.spawn_shared(self.cpu, Box::pin(future));
+        handle
+    }
+
+    /// Makes this spawner the one used by [`spawn`].
+    ///
+    /// Panics if a global spawner was already set.
+    pub fn set_global(self) {
+        GLOBAL
+            .try_init_once(|| self)
+            .expect("global spawner already set");
+    }
+}
+
+/// Spawns `future` onto the executor registered with [`Spawner::set_global`].
+///
+/// Safe to call from interrupt handlers.
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    GLOBAL
+        .try_get()
+        .expect("global spawner not set")
+        .spawn(future)
+}
@@ -0,0 +1,25 @@
+//! Async-aware synchronization primitives.
+//!
+//! Unlike `spin::Mutex`, waiting on these types registers the task's waker
+//! and returns `Pending`, so a lock can be held across an `.await` without
+//! blocking the executor. All waiters are served in FIFO order: a task that
+//! starts waiting first acquires first, and later arrivals cannot barge
+//! ahead of queued ones.
+
+mod mutex;
+mod notify;
+mod rwlock;
+mod semaphore;
+
+pub use mutex::{Mutex, MutexGuard};
+pub use notify::{Notified, Notify};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::{Semaphore, SemaphorePermit, TryAcquireError};
+
+use x86_64::instructions::interrupts::without_interrupts;
+
+/// Locks `lock` with interrupts disabled, so the primitives can also be
+/// released or notified from interrupt handlers.
+pub(super) fn with_locked<T, R>(lock: &spin::Mutex<T>, f: impl FnOnce(&mut T) -> R) -> R {
+    without_interrupts(|| f(&mut lock.lock()))
+}
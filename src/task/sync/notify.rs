@@ -0,0 +1,147 @@
+use super::with_locked;
+use alloc::{collections::VecDeque, sync::Arc};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU8, Ordering},
+    task::{Context, Poll},
+};
+use futures_util::task::AtomicWaker;
+
+const NOT_NOTIFIED: u8 = 0;
+const NOTIFIED_ONE: u8 = 1;
+const NOTIFIED_ALL: u8 = 2;
+
+/// Wakes tasks waiting for an event.
+///
+/// [`notify_one`](Notify::notify_one) wakes the oldest waiter, or stores a
+/// single permit for the next [`notified`](Notify::notified) call if nobody
+/// waits. [`notify_waiters`](Notify::notify_waiters) wakes every task that
+/// is currently waiting and stores nothing. Both may be called from
+/// interrupt handlers.
+pub struct Notify {
+    state: spin::Mutex<State>,
+}
+
+struct State {
+    permit: bool,
+    waiters: VecDeque<Arc<Waiter>>,
+}
+
+struct Waiter {
+    notified: AtomicU8,
+    waker: AtomicWaker,
+}
+
+impl State {
+    fn notify_one(&mut self) {
+        match self.waiters.pop_front() {
+            Some(waiter) => waiter.notify(NOTIFIED_ONE),
+            None => self.permit = true,
+        }
+    }
+}
+
+impl Waiter {
+    fn notify(&self, how: u8) {
+        self.notified.store(how, Ordering::Release);
+        self.waker.wake();
+    }
+}
+
+impl Notify {
+    pub const fn new() -> Self {
+        Notify {
+            state: spin::Mutex::new(State {
+                permit: false,
+                waiters: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Waits for a notification.
+    ///
+    /// The future joins the wait queue when it is first polled.
+    pub fn notified(&self) -> Notified<'_> {
+        Notified {
+            notify: self,
+            waiter: None,
+        }
+    }
+
+    pub fn notify_one(&self) {
+        with_locked(&self.state, |state| state.notify_one());
+    }
+
+    pub fn notify_waiters(&self) {
+        with_locked(&self.state, |state| {
+            for waiter in state.waiters.drain(..) {
+                waiter.notify(NOTIFIED_ALL);
+            }
+        });
+    }
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Notify::new()
+    }
+}
+
+/// Future returned by [`Notify::notified`].
+pub struct Notified<'a> {
+    notify: &'a Notify,
+    waiter: Option<Arc<Waiter>>,
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if let Some(waiter) = &self.waiter {
+            waiter.waker.register(cx.waker());
+            if waiter.notified.load(Ordering::Acquire) != NOT_NOTIFIED {
+                self.waiter = None;
+                return Poll::Ready(());
+            }
+            return Poll::Pending;
+        }
+
+        let waiter = with_locked(&self.notify.state, |state| {
+            if state.permit {
+                state.permit = false;
+                return None;
+            }
+            let waiter = Arc::new(Waiter {
+                notified: AtomicU8::new(NOT_NOTIFIED),
+                waker: AtomicWaker::new(),
+            });
+            waiter.waker.register(cx.waker());
+            state.waiters.push_back(waiter.clone());
+            Some(waiter)
+        });
+        match waiter {
+            None => Poll::Ready(()),
+            Some(waiter) => {
+                self.waiter = Some(waiter);
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        let Some(waiter) = self.waiter.take() else {
+            return;
+        };
+        with_locked(&self.notify.state, |state| {
+            match waiter.notified.load(Ordering::Acquire) {
+                NOT_NOTIFIED => state.waiters.retain(|queued| !Arc::ptr_eq(queued, &waiter)),
+                // a `notify_one` meant for us must not get lost
+                NOTIFIED_ONE => state.notify_one(),
+                _ => {}
+            }
+        });
+    }
+}
@@ -0,0 +1,216 @@
+use super::with_locked;
+use crate::task::coop;
+use alloc::{collections::VecDeque, sync::Arc};
+use core::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll},
+};
+use futures_util::{ready, task::AtomicWaker};
+
+/// Counting semaphore with FIFO waiters.
+pub struct Semaphore {
+    state: spin::Mutex<State>,
+}
+
+struct State {
+    permits: usize,
+    waiters: VecDeque<Arc<Waiter>>,
+}
+
+struct Waiter {
+    permits: usize,
+    granted: AtomicBool,
+    waker: AtomicWaker,
+}
+
+impl State {
+    /// Hands permits to queued waiters, oldest first, until the oldest one
+    /// needs more than is available.
+    fn grant_waiters(&mut self) {
+        while let Some(waiter) = self.waiters.front() {
+            if waiter.permits > self.permits {
+                break;
+            }
+            self.permits -= waiter.permits;
+            waiter.granted.store(true, Ordering::Release);
+            waiter.waker.wake();
+            self.waiters.pop_front();
+        }
+    }
+}
+
+/// Error returned by [`Semaphore::try_acquire`] when no permits are available.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TryAcquireError;
+
+impl fmt::Display for TryAcquireError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("no permits available")
+    }
+}
+
+/// Permits held on a [`Semaphore`], returned when dropped.
+#[must_use]
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Semaphore {
+            state: spin::Mutex::new(State {
+                permits,
+                waiters: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Number of permits that are currently free.
+    pub fn available_permits(&self) -> usize {
+        with_locked(&self.state, |state| state.permits)
+    }
+
+    /// Waits for one permit.
+    pub async fn acquire(&self) -> SemaphorePermit<'_> {
+        self.acquire_many(1).await
+    }
+
+    /// Waits until `permits` permits can be taken at once.
+    pub async fn acquire_many(&self, permits: usize) -> SemaphorePermit<'_> {
+        self.acquire_raw(permits).await;
+        SemaphorePermit {
+            semaphore: self,
+            permits,
+        }
+    }
+
+    /// Takes one permit if it is available and nobody is queued.
+    pub fn try_acquire(&self) -> Result<SemaphorePermit<'_>, TryAcquireError> {
+        self.try_acquire_many(1)
+    }
+
+    /// Takes `permits` permits if they are available and nobody is queued.
+    pub fn try_acquire_many(&self, permits: usize) -> Result<SemaphorePermit<'_>, TryAcquireError> {
+        match self.try_acquire_raw(permits) {
+            true => Ok(SemaphorePermit {
+                semaphore: self,
+                permits,
+            }),
+            false => Err(TryAcquireError),
+        }
+    }
+
+    /// Adds `permits` new permits, waking waiters that can now proceed.
+    pub fn add_permits(&self, permits: usize) {
+        self.release(permits);
+    }
+
+    pub(super) fn acquire_raw(&self, permits: usize) -> Acquire<'_> {
+        Acquire {
+            semaphore: self,
+            permits,
+            waiter: None,
+        }
+    }
+
+    pub(super) fn try_acquire_raw(&self, permits: usize) -> bool {
+        with_locked(&self.state, |state| {
+            if state.waiters.is_empty() && state.permits >= permits {
+                state.permits -= permits;
+                true
+            } else {
+                false
+            }
+        })
+    }
+
+    pub(super) fn release(&self, permits: usize) {
+        with_locked(&self.state, |state| {
+            state.permits += permits;
+            state.grant_waiters();
+        })
+    }
+}
+
+impl SemaphorePermit<'_> {
+    /// Drops the permits without returning them to the semaphore.
+    pub fn forget(mut self) {
+        self.permits = 0;
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        if self.permits > 0 {
+            self.semaphore.release(self.permits);
+        }
+    }
+}
+
+/// Future that waits until permits are granted to it.
+pub(super) struct Acquire<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+    waiter: Option<Arc<Waiter>>,
+}
+
+impl Future for Acquire<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if let Some(waiter) = &self.waiter {
+            waiter.waker.register(cx.waker());
+            if waiter.granted.load(Ordering::Acquire) {
+                self.waiter = None;
+                return Poll::Ready(());
+            }
+            return Poll::Pending;
+        }
+
+        ready!(coop::poll_proceed(cx));
+        let permits = self.permits;
+        let waiter = with_locked(&self.semaphore.state, |state| {
+            if state.waiters.is_empty() && state.permits >= permits {
+                state.permits -= permits;
+                return None;
+            }
+            let waiter = Arc::new(Waiter {
+                permits,
+                granted: AtomicBool::new(false),
+                waker: AtomicWaker::new(),
+            });
+            waiter.waker.register(cx.waker());
+            state.waiters.push_back(waiter.clone());
+            Some(waiter)
+        });
+        match waiter {
+            None => Poll::Ready(()),
+            Some(waiter) => {
+                self.waiter = Some(waiter);
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        let Some(waiter) = self.waiter.take() else {
+            return;
+        };
+        with_locked(&self.semaphore.state, |state| {
+            if waiter.granted.load(Ordering::Acquire) {
+                // granted but never observed, give the permits back
+                state.permits += waiter.permits;
+            } else {
+                state.waiters.retain(|queued| !Arc::ptr_eq(queued, &waiter));
+            }
+            // a large request leaving the head of the queue may unblock others
+            state.grant_waiters();
+        });
+    }
+}
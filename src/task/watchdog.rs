@@ -0,0 +1,127 @@
+//! Detection of tasks that poll for too long.
+//!
+//! The executor is cooperative, so a future that loops without returning
+//! `Pending` freezes everything else. The executor records which task it is
+//! polling and when the poll started; the timer interrupt compares that
+//! against a threshold and reports a poll that overran it, once per poll.
+//!
+//! Detection is only as precise as the timer period, and only covers the
+//! bootstrap processor, the one CPU that receives timer interrupts.
+
+use super::TaskId;
+use crate::{backtrace, cpu_local, println, serial_println, time::Instant};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::structures::idt::InterruptStackFrame;
+
+/// Default [`threshold`].
+pub const DEFAULT_THRESHOLD: Duration = Duration::from_millis(100);
+
+/// Threshold in nanoseconds, 0 disables the watchdog.
+static THRESHOLD: AtomicU64 = AtomicU64::new(DEFAULT_THRESHOLD.as_nanos() as u64);
+static PANIC: AtomicBool = AtomicBool::new(false);
+static STALLS: AtomicU64 = AtomicU64::new(0);
+
+cpu_local! {
+    /// Poll running on the CPU. Only locked with interrupts disabled or
+    /// from the timer interrupt.
+    static CURRENT: spin::Mutex<Option<Poll>> = spin::Mutex::new(None);
+}
+
+struct Poll {
+    task_id: TaskId,
+    name: Option<&'static str>,
+    start: Instant,
+    reported: bool,
+}
+
+/// Sets the longest a single poll may take, `None` disables the watchdog.
+pub fn set_threshold(threshold: Option<Duration>) {
+    let nanos = threshold.map_or(0, |threshold| threshold.as_nanos().max(1) as u64);
+    THRESHOLD.store(nanos, Ordering::Relaxed);
+}
+
+pub fn threshold() -> Option<Duration> {
+    match THRESHOLD.load(Ordering::Relaxed) {
+        0 => None,
+        nanos => Some(Duration::from_nanos(nanos)),
+    }
+}
+
+/// Makes a detected overrun panic instead of only being reported. Meant for
+/// tests.
+pub fn set_panic_on_stall(panic: bool) {
+    PANIC.store(panic, Ordering::Relaxed);
+}
+
+/// Number of overrunning polls detected since boot.
+pub fn stalls() -> u64 {
+    STALLS.load(Ordering::Relaxed)
+}
+
+/// Records that the executor starts polling a task.
+pub(super) fn poll_started(task_id: TaskId, name: Option<&'static str>) {
+    let poll = Poll {
+        task_id,
+        name,
+        start: Instant::now(),
+        reported: false,
+    };
+    without_interrupts(|| *CURRENT.get().lock() = Some(poll));
+}
+
+/// Records that the poll started by [`poll_started`] returned.
+pub(super) fn poll_finished() {
+    without_interrupts(|| *CURRENT.get().lock() = None);
+}
+
+/// Checks the poll running on the calling CPU against the threshold. Called
+/// by the timer interrupt handler with the frame of the interrupted code.
+pub fn check(stack_frame: &InterruptStackFrame) {
+    let Some(threshold) = threshold() else {
+        return;
+    };
+    // the lock is never held with interrupts enabled, this is only paranoia
+    let Some(mut current) = CURRENT.get().try_lock() else {
+        return;
+    };
+    let Some(poll) = current.as_mut().filter(|poll| !poll.reported) else {
+        return;
+    };
+    let elapsed = poll.start.elapsed();
+    if elapsed < threshold {
+        return;
+    }
+    poll.reported = true;
+    let (task_id, name) = (poll.task_id, poll.name.unwrap_or("<unnamed>"));
+    drop(current);
+    STALLS.fetch_add(1, Ordering::Relaxed);
+
+    let rip = stack_frame.instruction_pointer.as_u64();
+    let (symbol, offset) = backtrace::resolve(rip).unwrap_or((backtrace::Symbol::UNKNOWN, 0));
+    println!(
+        "watchdog: task {} ({}) polling for {} ms, RIP {:#x} {}+{:#x}",
+        task_id,
+        name,
+        elapsed.as_millis(),
+        rip,
+        symbol,
+        offset
+    );
+    serial_println!(
+        "watchdog: task {} ({}) polling for {} ms, RIP {:#x} {}+{:#x}",
+        task_id,
+        name,
+        elapsed.as_millis(),
+        rip,
+        symbol,
+        offset
+    );
+    if PANIC.load(Ordering::Relaxed) {
+        panic!(
+            "task {} ({}) exceeded the watchdog threshold",
+            task_id, name
+        );
+    }
+}
@@ -0,0 +1,192 @@
+//! Stack switching and FPU state.
+
+use alloc::{alloc::Layout, boxed::Box, vec};
+use conquer_once::spin::OnceCell;
+use core::arch::x86_64::{__cpuid, __cpuid_count};
+use core::arch::{asm, naked_asm};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+use x86_64::registers::xcontrol::{XCr0, XCr0Flags};
+
+/// Written to the lowest word of every thread stack and checked whenever the
+/// thread is switched out.
+const STACK_CANARY: u64 = 0x57ac_4ca5_a2f0_0d1e;
+
+/// Size of the XSAVE area, 0 if the CPU only has FXSAVE.
+static XSAVE_SIZE: AtomicUsize = AtomicUsize::new(0);
+/// Clean FPU state every new thread starts from.
+static INITIAL_FPU: OnceCell<FpuState> = OnceCell::uninit();
+
+const FXSAVE_SIZE: usize = 512;
+
+/// Enables SSE and, where supported, XSAVE, and records the initial FPU
+/// state.
+pub(super) fn init_fpu() {
+    unsafe {
+        Cr0::update(|flags| {
+            flags.remove(Cr0Flags::EMULATE_COPROCESSOR | Cr0Flags::TASK_SWITCHED);
+            flags.insert(Cr0Flags::MONITOR_COPROCESSOR);
+        });
+        Cr4::update(|flags| flags.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE));
+    }
+
+    let features = __cpuid(1).ecx;
+    if features & (1 << 26) != 0 {
+        let mut xcr0 = XCr0Flags::X87 | XCr0Flags::SSE;
+        if features & (1 << 28) != 0 {
+            xcr0 |= XCr0Flags::AVX;
+        }
+        unsafe {
+            Cr4::update(|flags| flags.insert(Cr4Flags::OSXSAVE));
+            XCr0::write(xcr0);
+        }
+        // size needed for the components enabled in XCR0
+        XSAVE_SIZE.store(__cpuid_count(0xd, 0).ebx as usize, Ordering::Relaxed);
+    }
+
+    let mxcsr: u32 = 0x1f80;
+    unsafe {
+        asm!("fninit", "ldmxcsr [{}]", in(reg) &mxcsr, options(nostack));
+    }
+    let mut initial = FpuState::alloc();
+    initial.save();
+    INITIAL_FPU.init_once(|| initial);
+}
+
+/// Uses XSAVE if the CPU supports it.
+fn xsave_size() -> Option<usize> {
+    match XSAVE_SIZE.load(Ordering::Relaxed) {
+        0 => None,
+        size => Some(size),
+    }
+}
+
+/// x87, SSE and (with XSAVE) AVX registers of a switched-out thread.
+pub(super) struct FpuState {
+    area: *mut u8,
+    layout: Layout,
+}
+
+unsafe impl Send for FpuState {}
+unsafe impl Sync for FpuState {}
+
+impl FpuState {
+    /// Clean state for a new thread.
+    pub(super) fn new() -> FpuState {
+        let initial = INITIAL_FPU.get().expect("FPU not initialized");
+        let state = FpuState::alloc();
+        unsafe { core::ptr::copy_nonoverlapping(initial.area, state.area, state.layout.size()) };
+        state
+    }
+
+    fn alloc() -> FpuState {
+        let size = xsave_size().unwrap_or(FXSAVE_SIZE);
+        let layout = Layout::from_size_align(size, 64).unwrap();
+        let area = unsafe { alloc::alloc::alloc_zeroed(layout) };
+        if area.is_null() {
+            alloc::alloc::handle_alloc_error(layout);
+        }
+        FpuState { area, layout }
+    }
+
+    pub(super) fn save(&mut self) {
+        unsafe {
+            match xsave_size() {
+                Some(_) => asm!(
+                    "xsave64 [{}]",
+                    in(reg) self.area, in("eax") u32::MAX, in("edx") u32::MAX,
+                    options(nostack),
+                ),
+                None => asm!("fxsave64 [{}]", in(reg) self.area, options(nostack)),
+            }
+        }
+    }
+
+    pub(super) fn restore(&self) {
+        unsafe {
+            match xsave_size() {
+                Some(_) => asm!(
+                    "xrstor64 [{}]",
+                    in(reg) self.area, in("eax") u32::MAX, in("edx") u32::MAX,
+                    options(nostack),
+                ),
+                None => asm!("fxrstor64 [{}]", in(reg) self.area, options(nostack)),
+            }
+        }
+    }
+}
+
+impl Drop for FpuState {
+    fn drop(&mut self) {
+        unsafe { alloc::alloc::dealloc(self.area, self.layout) };
+    }
+}
+
+/// Heap-allocated stack of a spawned thread.
+pub(super) struct Stack {
+    words: Box<[u64]>,
+}
+
+impl Stack {
+    pub(super) fn new(size: usize) -> Stack {
+        let mut words = vec![0u64; size / 8].into_boxed_slice();
+        words[0] = STACK_CANARY;
+        Stack { words }
+    }
+
+    /// Prepares the stack so that switching to it calls `entry(arg)`, and
+    /// returns the initial stack pointer.
+    pub(super) fn init(&mut self, entry: extern "C" fn(u64) -> !, arg: u64) -> u64 {
+        let top = self.words.as_mut_ptr_range().end as u64 & !0xf;
+        // [top - 8]: return address of `switch_stacks`, below it the six
+        // callee-saved registers it pops
+        let frame = (top - 8 * 7) as *mut u64;
+        unsafe {
+            frame.add(6).write(thread_start as *const () as u64);
+            // r15, r14, r13, r12, rbx, rbp; rbp = 0 ends backtraces
+            frame.add(0).write(0);
+            frame.add(1).write(0);
+            frame.add(2).write(entry as *const () as u64);
+            frame.add(3).write(arg);
+            frame.add(4).write(0);
+            frame.add(5).write(0);
+        }
+        frame as u64
+    }
+
+    pub(super) fn overflowed(&self) -> bool {
+        self.words[0] != STACK_CANARY
+    }
+}
+
+/// First code run by a new thread: calls `r13(r12)` on a 16-byte aligned
+/// stack.
+#[unsafe(naked)]
+unsafe extern "C" fn thread_start() -> ! {
+    naked_asm!("mov rdi, r12", "call r13", "ud2")
+}
+
+/// Saves the callee-saved registers on the current stack, stores the stack
+/// pointer to `old_rsp` and resumes the context saved at `new_rsp`.
+///
+/// Must be called with interrupts disabled.
+#[unsafe(naked)]
+pub(super) unsafe extern "C" fn switch_stacks(old_rsp: *mut u64, new_rsp: u64) {
+    naked_asm!(
+        "push rbp",
+        "push rbx",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov [rdi], rsp",
+        "mov rsp, rsi",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbx",
+        "pop rbp",
+        "ret",
+    )
+}
@@ -0,0 +1,309 @@
+//! Preemptive kernel threads.
+//!
+//! Every thread has its own stack and FPU state. Threads are scheduled round
+//! robin: the timer interrupt preempts the running thread every tick, and a
+//! thread gives up the rest of its slice by sleeping, parking, joining or
+//! [`yield_now`]. The boot thread becomes thread 0 in [`init`]; an idle
+//! thread runs whenever no other thread is ready.
+//!
+//! Every CPU has its own scheduler and threads never migrate. So far only
+//! the bootstrap processor calls [`init`]; on other CPUs [`halt`] just halts
+//! and everything else that needs a scheduler panics.
+//!
+//! The scheduler lock is only taken with interrupts disabled, so spin locks
+//! that are also only held with interrupts disabled can never be held by a
+//! preempted thread.
+
+mod context;
+mod scheduler;
+
+use crate::{cpu_local, percpu, smp};
+use alloc::{boxed::Box, sync::Arc};
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
+use scheduler::{Scheduler, State, Thread};
+use x86_64::instructions::interrupts::{self, without_interrupts};
+
+/// Timer interrupts per second, i.e. the number of time slices.
+pub const TICK_HZ: u32 = 100;
+
+/// Stack size of threads spawned without [`Builder::stack_size`].
+pub const DEFAULT_STACK_SIZE: usize = 32 * 1024;
+
+cpu_local! {
+    static SCHEDULER: spin::Mutex<Option<Scheduler>> = spin::Mutex::new(None);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    const BOOT: ThreadId = ThreadId(0);
+
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        ThreadId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl fmt::Display for ThreadId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// Turns the caller into the boot thread of the calling CPU, creates the
+/// idle thread and starts the timer at [`TICK_HZ`].
+pub fn init() {
+    context::init_fpu();
+    let idle = Thread::new(Some("idle"), 16 * 1024, idle_main, 0);
+    without_interrupts(|| *SCHEDULER.get().lock() = Some(Scheduler::new(Thread::boot(), idle)));
+    crate::drivers::pit::set_periodic(TICK_HZ);
+}
+
+/// Runs `f` on the calling CPU's scheduler with interrupts disabled.
+fn with_scheduler<R>(f: impl FnOnce(&mut Scheduler) -> R) -> R {
+    without_interrupts(|| {
+        let mut scheduler = SCHEDULER.get().lock();
+        f(scheduler.as_mut().expect("threads not initialized"))
+    })
+}
+
+/// Updates the state of the current thread and switches to the next one.
+/// Returns when the current thread runs again.
+///
+/// Must not be called while preemption is disabled: the preemption count
+/// belongs to the CPU, so the next thread would inherit it.
+fn reschedule(update: impl FnOnce(&mut Thread)) {
+    debug_assert_eq!(
+        percpu::preempt_count(),
+        0,
+        "thread switch with preemption disabled"
+    );
+    without_interrupts(|| {
+        let switch = {
+            let mut scheduler = SCHEDULER.get().lock();
+            let scheduler = scheduler.as_mut().expect("threads not initialized");
+            update(scheduler.current());
+            scheduler.schedule()
+        };
+        if let Some(switch) = switch {
+            unsafe { switch.run() };
+        }
+    });
+}
+
+/// Preempts the running thread. Called by the timer interrupt handler after
+/// the end of interrupt was signalled.
+///
+/// Does nothing while preemption is disabled, see
+/// [`percpu::disable_preemption`], or if the timer interrupted another
+/// interrupt handler.
+pub fn tick() {
+    if !percpu::preemptible() {
+        return;
+    }
+    let switch = match SCHEDULER.get().try_lock() {
+        Some(mut scheduler) => match scheduler.as_mut() {
+            Some(scheduler) => scheduler.schedule(),
+            None => return,
+        },
+        // interrupts are disabled while the lock is held, so nobody can
+        // hold it here; this is only paranoia
+        None => return,
+    };
+    if let Some(switch) = switch {
+        unsafe { switch.run() };
+    }
+}
+
+/// Configures a thread before spawning it.
+#[derive(Debug, Clone, Copy)]
+pub struct Builder {
+    name: Option<&'static str>,
+    stack_size: usize,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Builder {
+            name: None,
+            stack_size: DEFAULT_STACK_SIZE,
+        }
+    }
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Builder::default()
+    }
+
+    pub fn name(mut self, name: &'static str) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// Sets the stack size, [`DEFAULT_STACK_SIZE`] by default.
+    pub fn stack_size(mut self, size: usize) -> Self {
+        self.stack_size = size;
+        self
+    }
+
+    pub fn spawn<F, T>(self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let packet = Arc::new(Packet {
+            result: spin::Mutex::new(None),
+            finished: AtomicBool::new(false),
+            joiner: AtomicU64::new(NO_JOINER),
+        });
+        let their_packet = packet.clone();
+        let main: Box<dyn FnOnce()> = Box::new(move || {
+            let result = f();
+            without_interrupts(|| *their_packet.result.lock() = Some(result));
+            their_packet.finished.store(true, Ordering::SeqCst);
+            let joiner = their_packet.joiner.load(Ordering::SeqCst);
+            if joiner != NO_JOINER {
+                unpark(ThreadId(joiner));
+            }
+        });
+        let arg = Box::into_raw(Box::new(main)) as u64;
+        let thread = Thread::new(self.name, self.stack_size, thread_main, arg);
+        let id = thread.id;
+        with_scheduler(|scheduler| scheduler.add(thread));
+        JoinHandle { id, packet }
+    }
+}
+
+/// Spawns a thread running `f` and returns a handle to its result.
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    Builder::new().spawn(f)
+}
+
+extern "C" fn thread_main(arg: u64) -> ! {
+    // threads start inside a context switch, with interrupts disabled
+    interrupts::enable();
+    let main = unsafe { Box::from_raw(arg as *mut Box<dyn FnOnce()>) };
+    main();
+    exit();
+}
+
+extern "C" fn idle_main(_: u64) -> ! {
+    loop {
+        interrupts::disable();
+        halt();
+    }
+}
+
+const NO_JOINER: u64 = u64::MAX;
+
+struct Packet<T> {
+    /// Only locked with interrupts disabled.
+    result: spin::Mutex<Option<T>>,
+    finished: AtomicBool,
+    joiner: AtomicU64,
+}
+
+/// Owned permission to join a thread.
+pub struct JoinHandle<T> {
+    id: ThreadId,
+    packet: Arc<Packet<T>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.packet.finished.load(Ordering::SeqCst)
+    }
+
+    /// Blocks until the thread finishes and returns its result.
+    pub fn join(self) -> T {
+        self.packet.joiner.store(current().0, Ordering::SeqCst);
+        while !self.is_finished() {
+            park();
+        }
+        without_interrupts(|| self.packet.result.lock().take()).expect("thread result missing")
+    }
+}
+
+/// ID of the calling thread.
+pub fn current() -> ThreadId {
+    with_scheduler(|scheduler| scheduler.current)
+}
+
+/// Name of the calling thread.
+pub fn name() -> Option<&'static str> {
+    with_scheduler(|scheduler| scheduler.current().name)
+}
+
+/// Gives up the rest of the time slice.
+pub fn yield_now() {
+    reschedule(|_| {});
+}
+
+/// Blocks the calling thread for at least `duration`.
+///
+/// Sleepers are woken on timer ticks, so the sleep is rounded up to the
+/// next tick.
+pub fn sleep(duration: Duration) {
+    let until = crate::time::Instant::now() + duration;
+    reschedule(|thread| thread.state = State::Sleeping(until));
+}
+
+/// Blocks until [`unpark`] is called for this thread, unless it was already
+/// called since the last `park`. May also return spuriously.
+pub fn park() {
+    reschedule(|thread| {
+        if thread.unpark_token {
+            thread.unpark_token = false;
+        } else {
+            thread.state = State::Parked;
+        }
+    });
+}
+
+/// Unparks thread `id`, which may run on any CPU.
+pub fn unpark(id: ThreadId) {
+    without_interrupts(|| {
+        for cpu in 0..smp::online_cpus() {
+            if let Some(scheduler) = SCHEDULER.for_cpu(cpu).lock().as_mut() {
+                scheduler.unpark(id);
+            }
+        }
+    });
+}
+
+/// Gives the CPU to other ready threads, or halts until the next interrupt
+/// if there are none.
+///
+/// Must be called with interrupts disabled, returns with interrupts
+/// enabled. This lets an idle loop check for work without missing a wakeup.
+pub fn halt() {
+    let has_ready = SCHEDULER
+        .get()
+        .lock()
+        .as_ref()
+        .is_some_and(|scheduler| scheduler.has_ready());
+    if has_ready {
+        interrupts::enable();
+        yield_now();
+    } else {
+        interrupts::enable_and_hlt();
+    }
+}
+
+/// Ends the calling thread.
+pub fn exit() -> ! {
+    reschedule(|thread| thread.state = State::Finished);
+    unreachable!("finished thread was scheduled again");
+}
@@ -0,0 +1,199 @@
+use super::ThreadId;
+use super::context::{FpuState, Stack, switch_stacks};
+use crate::time::Instant;
+use alloc::{boxed::Box, collections::BTreeMap, collections::VecDeque};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum State {
+    Running,
+    Ready,
+    Sleeping(Instant),
+    Parked,
+    Finished,
+}
+
+pub(super) struct Thread {
+    pub(super) id: ThreadId,
+    pub(super) name: Option<&'static str>,
+    pub(super) state: State,
+    /// Set by `unpark` on a thread that was not parked, consumed by the next
+    /// `park`.
+    pub(super) unpark_token: bool,
+    /// Stack pointer while switched out.
+    rsp: u64,
+    fpu: FpuState,
+    /// `None` for the boot thread, which runs on the bootloader's stack.
+    stack: Option<Stack>,
+}
+
+impl Thread {
+    pub(super) fn boot() -> Thread {
+        Thread {
+            id: ThreadId::BOOT,
+            name: Some("boot"),
+            state: State::Running,
+            unpark_token: false,
+            rsp: 0,
+            fpu: FpuState::new(),
+            stack: None,
+        }
+    }
+
+    pub(super) fn new(
+        name: Option<&'static str>,
+        stack_size: usize,
+        entry: extern "C" fn(u64) -> !,
+        arg: u64,
+    ) -> Thread {
+        let mut stack = Stack::new(stack_size);
+        let rsp = stack.init(entry, arg);
+        Thread {
+            id: ThreadId::new(),
+            name,
+            state: State::Ready,
+            unpark_token: false,
+            rsp,
+            fpu: FpuState::new(),
+            stack: Some(stack),
+        }
+    }
+
+    fn runnable(&self) -> bool {
+        matches!(self.state, State::Running | State::Ready)
+    }
+}
+
+/// Round-robin scheduler state.
+///
+/// Only ever locked with interrupts disabled.
+pub(super) struct Scheduler {
+    pub(super) threads: BTreeMap<ThreadId, Box<Thread>>,
+    ready: VecDeque<ThreadId>,
+    pub(super) current: ThreadId,
+    /// Runs when no other thread is ready, never queued.
+    idle: ThreadId,
+}
+
+/// A context switch prepared under the scheduler lock, performed by
+/// [`Switch::run`] after the lock is released.
+pub(super) struct Switch {
+    old: *mut Thread,
+    new: *const Thread,
+}
+
+impl Scheduler {
+    pub(super) fn new(boot: Thread, idle: Thread) -> Scheduler {
+        let mut scheduler = Scheduler {
+            threads: BTreeMap::new(),
+            ready: VecDeque::new(),
+            current: boot.id,
+            idle: idle.id,
+        };
+        scheduler.threads.insert(boot.id, Box::new(boot));
+        scheduler.threads.insert(idle.id, Box::new(idle));
+        scheduler
+    }
+
+    pub(super) fn current(&mut self) -> &mut Thread {
+        self.threads.get_mut(&self.current).unwrap()
+    }
+
+    pub(super) fn add(&mut self, thread: Thread) {
+        let id = thread.id;
+        self.threads.insert(id, Box::new(thread));
+        self.ready.push_back(id);
+    }
+
+    /// Makes a parked thread ready, or leaves a token for its next park.
+    pub(super) fn unpark(&mut self, id: ThreadId) {
+        let Some(thread) = self.threads.get_mut(&id) else {
+            return;
+        };
+        match thread.state {
+            State::Parked => {
+                thread.state = State::Ready;
+                self.ready.push_back(id);
+            }
+            _ => thread.unpark_token = true,
+        }
+    }
+
+    pub(super) fn has_ready(&self) -> bool {
+        !self.ready.is_empty()
+    }
+
+    /// Picks the thread to run next, after the current thread's state has
+    /// been updated by the caller. A still running current thread goes to
+    /// the back of the ready queue.
+    pub(super) fn schedule(&mut self) -> Option<Switch> {
+        self.reap();
+        self.wake_sleepers();
+
+        let current = self.current;
+        let idle = self.idle;
+        let thread = self.current();
+        if thread.state == State::Running {
+            thread.state = State::Ready;
+            if current != idle {
+                self.ready.push_back(current);
+            }
+        }
+        let next = match self.ready.pop_front() {
+            Some(next) => next,
+            None if self.threads[&current].runnable() => current,
+            None => idle,
+        };
+        self.threads.get_mut(&next).unwrap().state = State::Running;
+        if next == current {
+            return None;
+        }
+
+        let old = self.current();
+        if old.stack.as_ref().is_some_and(Stack::overflowed) {
+            panic!("stack overflow in thread {}", old.id);
+        }
+        let old: *mut Thread = old;
+        self.current = next;
+        let new: *const Thread = self.current();
+        Some(Switch { old, new })
+    }
+
+    /// Drops threads that finished, except the current one whose stack is
+    /// still in use.
+    fn reap(&mut self) {
+        let current = self.current;
+        self.threads
+            .retain(|&id, thread| thread.state != State::Finished || id == current);
+    }
+
+    fn wake_sleepers(&mut self) {
+        let now = Instant::now();
+        for thread in self.threads.values_mut() {
+            if let State::Sleeping(until) = thread.state
+                && until <= now
+            {
+                thread.state = State::Ready;
+                self.ready.push_back(thread.id);
+            }
+        }
+    }
+}
+
+impl Switch {
+    /// Saves the current thread and resumes the next one. Returns once the
+    /// current thread is scheduled again.
+    ///
+    /// # Safety
+    ///
+    /// Must be called with interrupts disabled and the scheduler unlocked,
+    /// right after [`Scheduler::schedule`].
+    pub(super) unsafe fn run(self) {
+        unsafe {
+            let old = &mut *self.old;
+            let new = &*self.new;
+            old.fpu.save();
+            new.fpu.restore();
+            switch_stacks(&mut old.rsp, new.rsp);
+        }
+    }
+}
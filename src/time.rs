@@ -0,0 +1,208 @@
+//! High-resolution monotonic clock.
+//!
+//! At boot the TSC is calibrated against the HPET if ACPI reports one, or
+//! against the PIT otherwise. [`Instant`] then reads the invariant TSC, or
+//! falls back to the HPET main counter when the TSC may drift with frequency
+//! scaling and the HPET counter is 64 bits wide.
+
+use crate::drivers::{hpet, pit};
+use core::{
+    arch::x86_64::{__cpuid, _rdtsc},
+    ops::{Add, Sub},
+    sync::atomic::{AtomicU8, AtomicU64, Ordering},
+    time::Duration,
+};
+use x86_64::structures::paging::{FrameAllocator, Mapper, Size4KiB};
+
+/// Calibration window.
+const CALIBRATION_MS: u64 = 10;
+
+static TSC_HZ: AtomicU64 = AtomicU64::new(0);
+static SOURCE: AtomicU8 = AtomicU8::new(ClockSource::Uncalibrated as u8);
+
+/// Hardware counter backing [`Instant`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ClockSource {
+    /// [`init`] has not run yet, every instant is zero.
+    Uncalibrated,
+    Tsc,
+    Hpet,
+}
+
+/// Detects the HPET, calibrates the TSC and selects the clock source.
+pub fn init(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) {
+    let hpet = hpet::init(mapper, frame_allocator);
+    let tsc_hz = match hpet {
+        Some(hpet) => calibrate_with_hpet(hpet),
+        None => calibrate_with_pit(),
+    };
+    TSC_HZ.store(tsc_hz, Ordering::Relaxed);
+
+    // a 32-bit HPET wraps every few minutes, which `Instant` cannot tell
+    let source = match hpet {
+        Some(hpet) if hpet.is_64bit() && !tsc_is_invariant() => ClockSource::Hpet,
+        _ => ClockSource::Tsc,
+    };
+    SOURCE.store(source as u8, Ordering::Release);
+}
+
+/// The counter currently used by [`Instant`].
+pub fn clock_source() -> ClockSource {
+    match SOURCE.load(Ordering::Acquire) {
+        1 => ClockSource::Tsc,
+        2 => ClockSource::Hpet,
+        _ => ClockSource::Uncalibrated,
+    }
+}
+
+/// Calibrated TSC frequency in Hz, zero before [`init`].
+pub fn tsc_frequency() -> u64 {
+    TSC_HZ.load(Ordering::Relaxed)
+}
+
+/// Whether the TSC ticks at a constant rate regardless of power states
+/// (CPUID 8000_0007h, EDX bit 8).
+pub fn tsc_is_invariant() -> bool {
+    let max_extended = __cpuid(0x8000_0000).eax;
+    max_extended >= 0x8000_0007 && __cpuid(0x8000_0007).edx & (1 << 8) != 0
+}
+
+/// Raw time stamp counter.
+pub fn rdtsc() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// Busy-waits for at least `duration`.
+pub fn delay(duration: Duration) {
+    let deadline = Instant::now() + duration;
+    while Instant::now() < deadline {
+        core::hint::spin_loop();
+    }
+}
+
+fn calibrate_with_hpet(hpet: &hpet::Hpet) -> u64 {
+    let ticks = CALIBRATION_MS * hpet.frequency() / 1000;
+    let start = hpet.counter();
+    let tsc_start = rdtsc();
+    while hpet.ticks_since(start) < ticks {
+        core::hint::spin_loop();
+    }
+    let elapsed_fs = hpet.ticks_since(start) as u128 * hpet.period_fs() as u128;
+    let tsc_ticks = (rdtsc() - tsc_start) as u128;
+    (tsc_ticks * 1_000_000_000_000_000 / elapsed_fs) as u64
+}
+
+fn calibrate_with_pit() -> u64 {
+    let ticks = pit::FREQUENCY * CALIBRATION_MS / 1000;
+    let tsc_start = rdtsc();
+    pit::wait_ticks(ticks as u16);
+    (rdtsc() - tsc_start) * pit::FREQUENCY / ticks
+}
+
+/// A point in time with nanosecond resolution, measured from an arbitrary
+/// origin close to boot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant {
+    nanos: u64,
+}
+
+impl Instant {
+    pub fn now() -> Instant {
+        let nanos = match clock_source() {
+            ClockSource::Uncalibrated => 0,
+            ClockSource::Tsc => {
+                let hz = TSC_HZ.load(Ordering::Relaxed) as u128;
+                (rdtsc() as u128 * 1_000_000_000 / hz) as u64
+            }
+            ClockSource::Hpet => {
+                let hpet = hpet::get().expect("HPET clock source without HPET");
+                (hpet.counter() as u128 * hpet.period_fs() as u128 / 1_000_000) as u64
+            }
+        };
+        Instant { nanos }
+    }
+
+    /// Time elapsed since `earlier`, zero if `earlier` is later than `self`.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(self.nanos.saturating_sub(earlier.nanos))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    pub(crate) const fn from_nanos(nanos: u64) -> Instant {
+        Instant { nanos }
+    }
+
+    /// Nanoseconds since the clock origin.
+    pub fn as_nanos(&self) -> u64 {
+        self.nanos
+    }
+
+    /// `self + duration`, or `None` if that is past the end of the clock.
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        let nanos = u64::try_from(duration.as_nanos()).ok()?;
+        Some(Instant {
+            nanos: self.nanos.checked_add(nanos)?,
+        })
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    /// Saturates at the end of the clock, so deadlines too far away to be
+    /// represented are never reached.
+    fn add(self, rhs: Duration) -> Instant {
+        self.checked_add(rhs).unwrap_or(Instant { nanos: u64::MAX })
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, rhs: Duration) -> Instant {
+        Instant {
+            nanos: self
+                .nanos
+                .saturating_sub(u64::try_from(rhs.as_nanos()).unwrap_or(u64::MAX)),
+        }
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Duration {
+        self.duration_since(rhs)
+    }
+}
+
+#[test_case]
+fn test_add_saturates() {
+    let instant = Instant::from_nanos(10);
+    assert_eq!((instant + Duration::from_nanos(5)).as_nanos(), 15);
+    assert_eq!(instant.checked_add(Duration::MAX), None);
+    assert_eq!((instant + Duration::MAX).as_nanos(), u64::MAX);
+    assert_eq!((instant - Duration::MAX).as_nanos(), 0);
+}
+
+#[test_case]
+fn test_clock_calibrated() {
+    assert_ne!(clock_source(), ClockSource::Uncalibrated);
+    assert!(tsc_frequency() > 0);
+}
+
+#[test_case]
+fn test_delay() {
+    let start = Instant::now();
+    delay(Duration::from_millis(2));
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(2));
+    assert!(elapsed < Duration::from_millis(500));
+}
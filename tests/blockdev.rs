@@ -28,17 +28,10 @@ mod tests {
     use core::ptr;
     use alloc::sync::Arc;
     use alloc::{vec, vec::Vec};
-    use kos::task::{Task, executor::Executor};
-    use kos::drivers::blockdev::{BlockOp, RequestQueue, BlockRequest, ReqResult};
+    use kos::task::executor::Executor;
+    use kos::drivers::blockdev::{BlockOp, RequestQueue, BlockRequest};
     use core::panic::PanicInfo;
 
-    fn spawn_block_task<F>(executor: &mut Executor, fut: F)
-    where
-        F: core::future::Future<Output = ()> + 'static,
-    {
-        executor.spawn(Task::new(fut));
-    }
-
     #[test_case]
     fn basic_submit_and_complete() {
         let mut executor = Executor::new();
@@ -46,15 +39,8 @@ mod tests {
         let mut buf = [0u8; 512];
         let req = Arc::new(BlockRequest::new(BlockOp::Read, 0, 1, buf.as_mut_ptr(), buf.len()));
 
-        let result_holder = Arc::new(spin::Mutex::new(None));
-        let result_clone = result_holder.clone();
-        let queue_clone = queue.clone();
-
-        let future = queue_clone.submit(req.clone());
-        spawn_block_task(&mut executor, async move {
-            let res = future.await;
-            *result_clone.lock() = Some(res);
-        });
+        let future = queue.submit(req.clone());
+        let handle = executor.spawn(future);
 
         // Worker ends request
         if let Some(r) = queue.pop_one() {
@@ -62,12 +48,8 @@ mod tests {
             r.complete(r.blocks as i32);
         }
 
-        while result_holder.lock().as_ref().is_none() {
-            executor.run_ready_once();
-        }
-
+        assert_eq!(executor.block_on(handle), Ok(1));
         assert_eq!(buf[0], 0xAB);
-        assert_eq!(*result_holder.lock(), Some(1));
     }
 
     #[test_case]
@@ -75,20 +57,17 @@ mod tests {
         let mut executor = Executor::new();
         let queue = Arc::new(RequestQueue::new());
         let mut bufs: Vec<[u8; 512]> = vec![[0; 512]; 3];
-        let mut results: Vec<Arc<spin::Mutex<Option<ReqResult>>>> = vec![];
+        let mut handles = vec![];
         let mut requests: Vec<Arc<BlockRequest>> = vec![];
 
         for (i, buf) in bufs.iter_mut().enumerate() {
             let req = Arc::new(BlockRequest::new(BlockOp::Read, i as u64, 1, buf.as_mut_ptr(), buf.len()));
             requests.push(req.clone());
-            let result = Arc::new(spin::Mutex::new(None));
-            results.push(result.clone());
 
             let queue_clone = queue.clone();
-            spawn_block_task(&mut executor, async move {
-                let r = queue_clone.submit(req.clone()).await;
-                *result.lock() = Some(r);
-            });
+            handles.push(executor.spawn(async move {
+                queue_clone.submit(req.clone()).await
+            }));
         }
 
         // Worker ends all requests
@@ -97,14 +76,12 @@ mod tests {
             r.complete(r.blocks as i32);
         }
 
-        while results.iter().any(|r| r.lock().as_ref().is_none()) {
-            executor.run_ready_once();
-        }
+        let results: Vec<_> = handles.into_iter().map(|h| executor.block_on(h)).collect();
 
         // Check
         for (i, buf) in bufs.iter().enumerate() {
             assert_eq!(buf[0], (i as u8 + 2) as u8);
-            assert_eq!(results[i].lock().as_ref().unwrap(), &1);
+            assert_eq!(results[i], Ok(1));
         }
     }
 
@@ -0,0 +1,166 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec::Vec};
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use futures_util::stream::StreamExt;
+use kos::task::channel::{broadcast, mpsc, oneshot};
+use kos::task::executor::Executor;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use kos::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    kos::init(&mut mapper, &mut frame_allocator);
+
+    test_main();
+    kos::hlt_loop();
+}
+
+#[test_case]
+fn oneshot_delivers_value() {
+    let mut executor = Executor::new();
+    let (tx, rx) = oneshot::channel();
+    let handle = executor.spawn(rx);
+    executor.run_ready_once();
+    assert!(!handle.is_finished());
+    tx.send(42).unwrap();
+    assert_eq!(executor.block_on(handle), Ok(Ok(42)));
+}
+
+#[test_case]
+fn oneshot_sender_dropped() {
+    let mut executor = Executor::new();
+    let (tx, rx) = oneshot::channel::<u32>();
+    drop(tx);
+    assert_eq!(executor.block_on(rx), Err(oneshot::RecvError));
+}
+
+#[test_case]
+fn oneshot_receiver_dropped() {
+    let (tx, rx) = oneshot::channel();
+    drop(rx);
+    assert!(tx.is_closed());
+    assert_eq!(tx.send(7), Err(7));
+}
+
+#[test_case]
+fn mpsc_preserves_order() {
+    let mut executor = Executor::new();
+    let (tx, rx) = mpsc::channel(4);
+    let received = executor.spawn(rx.collect::<Vec<u32>>());
+    let tx2 = tx.clone();
+    executor.spawn(async move {
+        for i in 0..10 {
+            tx.send(i).await.unwrap();
+        }
+    });
+    executor.run_ready_once();
+    drop(tx2);
+    let received = executor.block_on(received).unwrap();
+    assert_eq!(received, (0..10).collect::<Vec<_>>());
+}
+
+#[test_case]
+fn mpsc_backpressure() {
+    let mut executor = Executor::new();
+    let (tx, mut rx) = mpsc::channel(2);
+    let sent = Arc::new(spin::Mutex::new(0));
+    let counter = sent.clone();
+    executor.spawn(async move {
+        for i in 0..5 {
+            tx.send(i).await.unwrap();
+            *counter.lock() += 1;
+        }
+    });
+    executor.run_ready_once();
+    assert_eq!(*sent.lock(), 2);
+    assert_eq!(rx.try_recv(), Ok(0));
+    executor.run_ready_once();
+    assert_eq!(*sent.lock(), 3);
+}
+
+#[test_case]
+fn mpsc_try_send() {
+    let (tx, mut rx) = mpsc::channel(1);
+    assert_eq!(tx.try_send(1), Ok(()));
+    assert_eq!(tx.try_send(2), Err(mpsc::TrySendError::Full(2)));
+    assert_eq!(rx.try_recv(), Ok(1));
+    assert_eq!(rx.try_recv(), Err(mpsc::TryRecvError::Empty));
+    drop(rx);
+    assert_eq!(tx.try_send(3), Err(mpsc::TrySendError::Closed(3)));
+}
+
+#[test_case]
+fn mpsc_send_fails_when_receiver_dropped() {
+    let mut executor = Executor::new();
+    let (tx, rx) = mpsc::channel(1);
+    tx.try_send(0).unwrap();
+    let handle = executor.spawn(async move { tx.send(1).await });
+    executor.run_ready_once();
+    assert!(!handle.is_finished());
+    drop(rx);
+    assert_eq!(executor.block_on(handle), Ok(Err(mpsc::SendError(1))));
+}
+
+#[test_case]
+fn mpsc_drains_after_senders_dropped() {
+    let mut executor = Executor::new();
+    let (tx, mut rx) = mpsc::channel(4);
+    tx.try_send(1).unwrap();
+    drop(tx);
+    let received = executor.block_on(async move { (rx.recv().await, rx.recv().await) });
+    assert_eq!(received, (Some(1), None));
+}
+
+#[test_case]
+fn broadcast_reaches_every_receiver() {
+    let mut executor = Executor::new();
+    let (tx, rx1) = broadcast::channel(8);
+    let rx2 = tx.subscribe();
+    let first = executor.spawn(rx1.collect::<Vec<_>>());
+    let second = executor.spawn(rx2.collect::<Vec<_>>());
+    executor.run_ready_once();
+    assert_eq!(tx.send(1), Ok(2));
+    assert_eq!(tx.send(2), Ok(2));
+    drop(tx);
+    assert_eq!(executor.block_on(first).unwrap(), [Ok(1), Ok(2)]);
+    assert_eq!(executor.block_on(second).unwrap(), [Ok(1), Ok(2)]);
+}
+
+#[test_case]
+fn broadcast_lagging_receiver() {
+    let (tx, mut rx) = broadcast::channel(2);
+    for i in 0..5 {
+        tx.send(i).unwrap();
+    }
+    assert_eq!(rx.try_recv(), Err(broadcast::TryRecvError::Lagged(3)));
+    assert_eq!(rx.try_recv(), Ok(3));
+    assert_eq!(rx.try_recv(), Ok(4));
+    assert_eq!(rx.try_recv(), Err(broadcast::TryRecvError::Empty));
+}
+
+#[test_case]
+fn broadcast_without_receivers() {
+    let (tx, rx) = broadcast::channel(1);
+    drop(rx);
+    assert_eq!(tx.receiver_count(), 0);
+    assert_eq!(tx.send(1), Err(broadcast::SendError(1)));
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kos::test_panic_handler(info)
+}
@@ -0,0 +1,291 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use kos::task::{Builder, Cancelled, Priority, coop, executor::Executor, yield_now};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use kos::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    kos::init(&mut mapper, &mut frame_allocator);
+
+    test_main();
+    kos::hlt_loop();
+}
+
+#[test_case]
+fn join_handle_returns_output() {
+    let mut executor = Executor::new();
+    let handle = executor.spawn(async { 40 + 2 });
+    assert!(!handle.is_finished());
+    executor.run_ready_once();
+    assert!(handle.is_finished());
+    assert_eq!(executor.block_on(handle), Ok(42));
+}
+
+#[test_case]
+fn join_handle_awaited_by_task() {
+    let mut executor = Executor::new();
+    let child = executor.spawn(async { String::from("child") });
+    let parent = executor.spawn(async move {
+        let mut name = child.await.expect("child cancelled");
+        name.push_str(" joined");
+        name
+    });
+    assert_eq!(executor.block_on(parent).as_deref(), Ok("child joined"));
+}
+
+#[test_case]
+fn block_on_many_handles() {
+    let mut executor = Executor::new();
+    let handles: Vec<_> = (0..10u64).map(|i| executor.spawn(async move { i * i })).collect();
+    let sum: u64 = handles.into_iter().map(|h| executor.block_on(h).unwrap()).sum();
+    assert_eq!(sum, 285);
+}
+
+#[test_case]
+fn spawn_from_running_task() {
+    let mut executor = Executor::new();
+    let spawner = executor.spawner();
+    let parent = executor.spawn(async move {
+        let children: Vec<_> = (1..=3u32).map(|i| spawner.spawn(async move { i * 10 })).collect();
+        let mut sum = 0;
+        for child in children {
+            sum += child.await.unwrap();
+        }
+        sum
+    });
+    assert_eq!(executor.block_on(parent), Ok(60));
+}
+
+#[test_case]
+fn spawn_through_global_spawner() {
+    let mut executor = Executor::new();
+    executor.spawner().set_global();
+    let handle = kos::task::spawn(async { "global" });
+    assert!(!handle.is_finished());
+    executor.run_ready_once();
+    assert!(handle.is_finished());
+    assert_eq!(executor.block_on(handle), Ok("global"));
+}
+
+#[test_case]
+fn spawn_from_interrupt_context() {
+    let mut executor = Executor::new();
+    let spawner = executor.spawner();
+    // the state an IRQ handler runs in
+    let handle = without_interrupts(|| {
+        let _irq = kos::percpu::enter_interrupt();
+        spawner.spawn(async { "from irq" })
+    });
+    assert_eq!(executor.block_on(handle), Ok("from irq"));
+}
+
+#[test_case]
+fn spawn_more_tasks_than_old_queue_capacity() {
+    let mut executor = Executor::new();
+    let handles: Vec<_> = (0..1000u64).map(|i| executor.spawn(async move { i })).collect();
+    let sum: u64 = handles.into_iter().map(|h| executor.block_on(h).unwrap()).sum();
+    assert_eq!(sum, 499_500);
+}
+
+/// Wakes itself `wakes_per_poll` times on each of `polls` polls.
+struct WakeStorm {
+    polls: usize,
+    wakes_per_poll: usize,
+    polled: &'static AtomicUsize,
+}
+
+impl core::future::Future for WakeStorm {
+    type Output = ();
+
+    fn poll(mut self: core::pin::Pin<&mut Self>, cx: &mut core::task::Context) -> core::task::Poll<()> {
+        self.polled.fetch_add(1, Ordering::SeqCst);
+        if self.polls == 0 {
+            return core::task::Poll::Ready(());
+        }
+        self.polls -= 1;
+        for _ in 0..self.wakes_per_poll {
+            cx.waker().wake_by_ref();
+        }
+        core::task::Poll::Pending
+    }
+}
+
+#[test_case]
+fn repeated_wakes_are_deduplicated() {
+    static POLLED: AtomicUsize = AtomicUsize::new(0);
+
+    let mut executor = Executor::new();
+    let handle = executor.spawn(WakeStorm {
+        polls: 10,
+        wakes_per_poll: 500,
+        polled: &POLLED,
+    });
+    executor.run_ready_once();
+    assert!(handle.is_finished());
+    assert_eq!(POLLED.load(Ordering::SeqCst), 11);
+}
+
+#[test_case]
+fn higher_priority_runs_first() {
+    static ORDER: Mutex<Vec<Priority>> = Mutex::new(Vec::new());
+
+    let mut executor = Executor::new();
+    for priority in [Priority::Idle, Priority::Normal, Priority::High, Priority::BottomHalf] {
+        Builder::new()
+            .priority(priority)
+            .spawn(&mut executor, async move { ORDER.lock().push(priority) });
+    }
+    executor.run_ready_once();
+    assert_eq!(
+        *ORDER.lock(),
+        [Priority::BottomHalf, Priority::High, Priority::Normal, Priority::Idle]
+    );
+}
+
+#[test_case]
+fn yield_now_lets_others_run() {
+    static LOG: Mutex<Vec<&str>> = Mutex::new(Vec::new());
+
+    let mut executor = Executor::new();
+    executor.spawn(async {
+        LOG.lock().push("a1");
+        yield_now().await;
+        LOG.lock().push("a2");
+    });
+    executor.spawn(async {
+        LOG.lock().push("b1");
+        yield_now().await;
+        LOG.lock().push("b2");
+    });
+    executor.run_ready_once();
+    assert_eq!(*LOG.lock(), ["a1", "b1", "a2", "b2"]);
+}
+
+/// Always has work to do until `stop` is set, spending budget on each unit.
+struct BusyTask {
+    stop: &'static AtomicBool,
+}
+
+impl core::future::Future for BusyTask {
+    type Output = ();
+
+    fn poll(self: core::pin::Pin<&mut Self>, cx: &mut core::task::Context) -> core::task::Poll<()> {
+        while !self.stop.load(Ordering::SeqCst) {
+            if coop::poll_proceed(cx).is_pending() {
+                return core::task::Poll::Pending;
+            }
+        }
+        core::task::Poll::Ready(())
+    }
+}
+
+#[test_case]
+fn budget_forces_busy_task_to_yield() {
+    static STOP: AtomicBool = AtomicBool::new(false);
+
+    let mut executor = Executor::new();
+    let busy = executor.spawn(BusyTask { stop: &STOP });
+    // without a budget the busy task would never return from its first poll
+    let quiet = executor.spawn(async { STOP.store(true, Ordering::SeqCst) });
+    executor.run_ready_once();
+    assert!(quiet.is_finished());
+    assert!(busy.is_finished());
+}
+
+/// Counts how many times it was dropped.
+struct DropCounter(&'static AtomicUsize);
+
+impl Drop for DropCounter {
+    fn drop(&mut self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[test_case]
+fn abort_pending_task() {
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    let mut executor = Executor::new();
+    let handle = executor.spawn(async {
+        let _guard = DropCounter(&DROPS);
+        core::future::pending::<()>().await;
+    });
+    executor.run_ready_once();
+    assert!(!handle.is_finished());
+    assert_eq!(DROPS.load(Ordering::SeqCst), 0);
+
+    handle.abort();
+    executor.run_ready_once();
+    assert!(handle.is_finished());
+    assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+    assert_eq!(executor.block_on(handle), Err(Cancelled));
+}
+
+#[test_case]
+fn abort_from_other_task() {
+    let mut executor = Executor::new();
+    let victim = executor.spawn(core::future::pending::<u32>());
+    let abort = victim.abort_handle();
+    executor.spawn(async move { abort.abort() });
+    let joiner = executor.spawn(victim);
+    assert_eq!(executor.block_on(joiner), Ok(Err(Cancelled)));
+}
+
+#[test_case]
+fn abort_finished_task() {
+    let mut executor = Executor::new();
+    let handle = executor.spawn(async { 7 });
+    executor.run_ready_once();
+    handle.abort();
+    assert_eq!(executor.block_on(handle), Ok(7));
+}
+
+#[test_case]
+fn task_stats() {
+    use kos::task::executor::TaskState;
+
+    let mut executor = Executor::new();
+    let handle = Builder::new().name("yielder").spawn(&mut executor, async {
+        yield_now().await;
+        core::future::pending::<()>().await;
+    });
+    executor.spawn(async {});
+    executor.run_ready_once();
+
+    let tasks = executor.tasks();
+    assert_eq!(tasks.len(), 1);
+    let info = &tasks[0];
+    assert_eq!(info.name, Some("yielder"));
+    assert_eq!(info.state, TaskState::Pending);
+    assert_eq!(info.polls, 2);
+    executor.dump_tasks();
+
+    handle.abort();
+    assert_eq!(executor.tasks()[0].state, TaskState::Ready);
+    executor.run_ready_once();
+    assert!(executor.tasks().is_empty());
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kos::test_panic_handler(info)
+}
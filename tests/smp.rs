@@ -0,0 +1,57 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use kos::smp;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use kos::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    kos::init(&mut mapper, &mut frame_allocator);
+
+    test_main();
+    kos::hlt_loop();
+}
+
+#[test_case]
+fn all_cpus_online() {
+    // the tests run with `-smp 4`
+    assert_eq!(smp::present_apic_ids().len(), 4);
+    assert_eq!(smp::online_cpus(), 4);
+}
+
+#[test_case]
+fn apic_ids_are_distinct() {
+    let mut ids: Vec<u8> = (0..smp::online_cpus())
+        .map(|cpu| smp::apic_id(cpu).unwrap())
+        .collect();
+    ids.sort_unstable();
+    ids.dedup();
+    assert_eq!(ids.len(), smp::online_cpus());
+    assert_eq!(smp::apic_id(smp::online_cpus()), None);
+}
+
+#[test_case]
+fn bsp_is_cpu_0() {
+    let lapic = kos::drivers::lapic::get().unwrap();
+    assert_eq!(smp::apic_id(0), Some(lapic.id()));
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kos::test_panic_handler(info)
+}
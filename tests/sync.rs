@@ -0,0 +1,200 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec::Vec};
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use kos::task::executor::Executor;
+use kos::task::sync::{Mutex, Notify, RwLock, Semaphore};
+use kos::task::yield_now;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use kos::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    kos::init(&mut mapper, &mut frame_allocator);
+
+    test_main();
+    kos::hlt_loop();
+}
+
+/// Shared event log, checked after the executor has run.
+type Log = Arc<spin::Mutex<Vec<&'static str>>>;
+
+fn log() -> Log {
+    Arc::new(spin::Mutex::new(Vec::new()))
+}
+
+#[test_case]
+fn mutex_held_across_await() {
+    let mut executor = Executor::new();
+    let mutex = Arc::new(Mutex::new(0));
+    for _ in 0..3 {
+        let mutex = mutex.clone();
+        executor.spawn(async move {
+            let mut guard = mutex.lock().await;
+            let value = *guard;
+            yield_now().await;
+            *guard = value + 1;
+        });
+    }
+    executor.run_ready_once();
+    assert_eq!(*mutex.try_lock().unwrap(), 3);
+}
+
+#[test_case]
+fn mutex_is_fifo() {
+    let mut executor = Executor::new();
+    let mutex = Arc::new(Mutex::new(()));
+    let order = log();
+
+    let guard = mutex.try_lock().unwrap();
+    for name in ["first", "second", "third"] {
+        let (mutex, order) = (mutex.clone(), order.clone());
+        executor.spawn(async move {
+            let _guard = mutex.lock().await;
+            order.lock().push(name);
+            yield_now().await;
+        });
+    }
+    executor.run_ready_once();
+    assert!(order.lock().is_empty());
+
+    // a new arrival must queue behind the waiting tasks
+    drop(guard);
+    assert!(mutex.try_lock().is_none());
+    executor.run_ready_once();
+    assert_eq!(*order.lock(), ["first", "second", "third"]);
+}
+
+#[test_case]
+fn rwlock_readers_share_writer_excludes() {
+    let mut executor = Executor::new();
+    let lock = Arc::new(RwLock::new(0));
+    let events = log();
+
+    let reader = lock.try_read().unwrap();
+    assert!(lock.try_read().is_some());
+    {
+        let (lock, events) = (lock.clone(), events.clone());
+        executor.spawn(async move {
+            *lock.write().await += 1;
+            events.lock().push("write");
+        });
+    }
+    {
+        let (lock, events) = (lock.clone(), events.clone());
+        executor.spawn(async move {
+            let value = *lock.read().await;
+            assert_eq!(value, 1);
+            events.lock().push("read");
+        });
+    }
+    executor.run_ready_once();
+    // the writer waits for the reader, the late reader waits for the writer
+    assert!(events.lock().is_empty());
+
+    drop(reader);
+    executor.run_ready_once();
+    assert_eq!(*events.lock(), ["write", "read"]);
+}
+
+#[test_case]
+fn semaphore_limits_concurrency() {
+    static SEMAPHORE: Semaphore = Semaphore::new(2);
+
+    let mut executor = Executor::new();
+    let active = Arc::new(spin::Mutex::new((0usize, 0usize)));
+    for _ in 0..6 {
+        let active = active.clone();
+        executor.spawn(async move {
+            let _permit = SEMAPHORE.acquire().await;
+            {
+                let mut active = active.lock();
+                active.0 += 1;
+                active.1 = active.1.max(active.0);
+            }
+            yield_now().await;
+            active.lock().0 -= 1;
+        });
+    }
+    executor.run_ready_once();
+    assert_eq!(*active.lock(), (0, 2));
+    assert_eq!(SEMAPHORE.available_permits(), 2);
+}
+
+#[test_case]
+fn semaphore_acquire_many_blocks_later_requests() {
+    let mut executor = Executor::new();
+    let semaphore = Arc::new(Semaphore::new(1));
+    let order = log();
+
+    for (name, permits) in [("big", 3), ("small", 1)] {
+        let (semaphore, order) = (semaphore.clone(), order.clone());
+        executor.spawn(async move {
+            semaphore.acquire_many(permits).await.forget();
+            order.lock().push(name);
+        });
+    }
+    executor.run_ready_once();
+    assert!(order.lock().is_empty());
+
+    semaphore.add_permits(3);
+    executor.run_ready_once();
+    assert_eq!(*order.lock(), ["big", "small"]);
+    assert_eq!(semaphore.available_permits(), 0);
+}
+
+#[test_case]
+fn notify_one_stores_permit() {
+    let mut executor = Executor::new();
+    let notify = Arc::new(Notify::new());
+    notify.notify_one();
+
+    let waiter = notify.clone();
+    let handle = executor.spawn(async move { waiter.notified().await });
+    executor.run_ready_once();
+    assert!(handle.is_finished());
+}
+
+#[test_case]
+fn notify_waiters_wakes_all() {
+    let mut executor = Executor::new();
+    let notify = Arc::new(Notify::new());
+    let handles: Vec<_> = (0..3)
+        .map(|_| {
+            let notify = notify.clone();
+            executor.spawn(async move { notify.notified().await })
+        })
+        .collect();
+    executor.run_ready_once();
+    assert!(handles.iter().all(|h| !h.is_finished()));
+
+    notify.notify_waiters();
+    executor.run_ready_once();
+    assert!(handles.iter().all(|h| h.is_finished()));
+
+    // notify_waiters does not store a permit
+    let late = notify.clone();
+    let late = executor.spawn(async move { late.notified().await });
+    executor.run_ready_once();
+    assert!(!late.is_finished());
+    late.abort();
+    executor.run_ready_once();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kos::test_panic_handler(info)
+}
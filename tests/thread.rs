@@ -0,0 +1,150 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec::Vec};
+use bootloader::{BootInfo, entry_point};
+use core::arch::asm;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
+use kos::task::executor::Executor;
+use kos::thread;
+use kos::time::Instant;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use kos::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    kos::init(&mut mapper, &mut frame_allocator);
+
+    test_main();
+    kos::hlt_loop();
+}
+
+#[test_case]
+fn spawn_and_join() {
+    let handle = thread::spawn(|| 6 * 7);
+    assert_eq!(handle.join(), 42);
+}
+
+#[test_case]
+fn join_many() {
+    let handles: Vec<_> = (0..8u64).map(|i| thread::spawn(move || i * i)).collect();
+    let sum: u64 = handles.into_iter().map(|handle| handle.join()).sum();
+    assert_eq!(sum, 140);
+}
+
+#[test_case]
+fn named_thread() {
+    let handle = thread::Builder::new().name("worker").spawn(thread::name);
+    assert_eq!(handle.join(), Some("worker"));
+    assert_eq!(thread::current(), thread::current());
+}
+
+#[test_case]
+fn sleep_waits() {
+    let start = Instant::now();
+    thread::sleep(Duration::from_millis(30));
+    assert!(start.elapsed() >= Duration::from_millis(30));
+}
+
+#[test_case]
+fn busy_threads_are_preempted() {
+    // neither thread yields, so both only progress through preemption
+    let stop = Arc::new(AtomicBool::new(false));
+    let counters: Vec<_> = (0..2).map(|_| Arc::new(AtomicU64::new(0))).collect();
+    let handles: Vec<_> = counters
+        .iter()
+        .map(|counter| {
+            let (counter, stop) = (counter.clone(), stop.clone());
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                }
+            })
+        })
+        .collect();
+    while counters
+        .iter()
+        .any(|counter| counter.load(Ordering::Relaxed) == 0)
+    {
+        core::hint::spin_loop();
+    }
+    stop.store(true, Ordering::Relaxed);
+    for handle in handles {
+        handle.join();
+    }
+}
+
+#[test_case]
+fn park_and_unpark() {
+    let parked = thread::spawn(|| {
+        thread::park();
+        1
+    });
+    thread::sleep(Duration::from_millis(20));
+    assert!(!parked.is_finished());
+    thread::unpark(parked.id());
+    assert_eq!(parked.join(), 1);
+}
+
+#[test_case]
+fn sse_registers_are_preserved() {
+    fn set_xmm0(value: u64) {
+        unsafe { asm!("movq xmm0, {}", in(reg) value) };
+    }
+    fn xmm0() -> u64 {
+        let value: u64;
+        unsafe { asm!("movq {}, xmm0", out(reg) value) };
+        value
+    }
+
+    let handle = thread::spawn(|| {
+        set_xmm0(0x1111);
+        thread::yield_now();
+        xmm0()
+    });
+    set_xmm0(0x2222);
+    thread::yield_now();
+    let other = handle.join();
+    assert_eq!(xmm0(), 0x2222);
+    assert_eq!(other, 0x1111);
+}
+
+#[test_case]
+fn executor_in_thread() {
+    let handle = thread::spawn(|| {
+        let mut executor = Executor::new();
+        executor.block_on(async { 5 })
+    });
+    assert_eq!(handle.join(), 5);
+}
+
+#[test_case]
+fn preemption_can_be_disabled() {
+    let ran = Arc::new(AtomicBool::new(false));
+    let flag = ran.clone();
+    let guard = kos::percpu::disable_preemption();
+    let handle = thread::spawn(move || flag.store(true, Ordering::SeqCst));
+    kos::time::delay(Duration::from_millis(50));
+    assert!(!ran.load(Ordering::SeqCst));
+    drop(guard);
+    handle.join();
+    assert!(ran.load(Ordering::SeqCst));
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kos::test_panic_handler(info)
+}
@@ -0,0 +1,74 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use core::time::Duration;
+use kos::task::{Builder, executor::Executor, watchdog, yield_now};
+use kos::time;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use kos::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    kos::init(&mut mapper, &mut frame_allocator);
+
+    test_main();
+    kos::hlt_loop();
+}
+
+#[test_case]
+fn long_poll_is_reported_once() {
+    watchdog::set_threshold(Some(Duration::from_millis(20)));
+    let mut executor = Executor::new();
+    let before = watchdog::stalls();
+    Builder::new().name("spinner").spawn(&mut executor, async {
+        time::delay(Duration::from_millis(300));
+    });
+    executor.run_ready_once();
+    assert_eq!(watchdog::stalls(), before + 1);
+    watchdog::set_threshold(Some(watchdog::DEFAULT_THRESHOLD));
+}
+
+#[test_case]
+fn yielding_task_is_not_reported() {
+    watchdog::set_threshold(Some(Duration::from_millis(20)));
+    let mut executor = Executor::new();
+    let before = watchdog::stalls();
+    executor.spawn(async {
+        for _ in 0..30 {
+            time::delay(Duration::from_millis(5));
+            yield_now().await;
+        }
+    });
+    executor.run_ready_once();
+    assert_eq!(watchdog::stalls(), before);
+    watchdog::set_threshold(Some(watchdog::DEFAULT_THRESHOLD));
+}
+
+#[test_case]
+fn disabled_watchdog() {
+    watchdog::set_threshold(None);
+    let mut executor = Executor::new();
+    let before = watchdog::stalls();
+    executor.spawn(async {
+        time::delay(Duration::from_millis(100));
+    });
+    executor.run_ready_once();
+    assert_eq!(watchdog::stalls(), before);
+    watchdog::set_threshold(Some(watchdog::DEFAULT_THRESHOLD));
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kos::test_panic_handler(info)
+}
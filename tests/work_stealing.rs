@@ -0,0 +1,111 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use kos::task::{executor::Executor, yield_now};
+use kos::{percpu, smp, time};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use kos::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    kos::init(&mut mapper, &mut frame_allocator);
+    smp::run_on_aps(run_ap_executor);
+
+    test_main();
+    kos::hlt_loop();
+}
+
+fn run_ap_executor() -> ! {
+    Executor::new().run()
+}
+
+/// Spins for `ms` milliseconds, keeping the CPU busy.
+fn busy(ms: u64) {
+    time::delay(core::time::Duration::from_millis(ms));
+}
+
+#[test_case]
+fn tasks_are_stolen_by_other_cpus() {
+    static CPUS: AtomicUsize = AtomicUsize::new(0);
+
+    let mut executor = Executor::new();
+    let spawner = executor.spawner();
+    let handles: Vec<_> = (0..16)
+        .map(|_| {
+            spawner.spawn(async {
+                busy(5);
+                CPUS.fetch_or(1 << percpu::current_cpu(), Ordering::Relaxed);
+            })
+        })
+        .collect();
+    executor.block_on(async move {
+        for handle in handles {
+            handle.await.unwrap();
+        }
+    });
+    assert!(CPUS.load(Ordering::Relaxed).count_ones() > 1);
+}
+
+#[test_case]
+fn task_migrates_between_cpus() {
+    static CPUS: AtomicUsize = AtomicUsize::new(0);
+
+    let mut executor = Executor::new();
+    let spawner = executor.spawner();
+    // keep the BSP busy, so that the yielding task gets stolen
+    let busy_tasks: Vec<_> = (0..4).map(|_| spawner.spawn(async { busy(20) })).collect();
+    let handle = spawner.spawn(async {
+        for _ in 0..100 {
+            CPUS.fetch_or(1 << percpu::current_cpu(), Ordering::Relaxed);
+            yield_now().await;
+        }
+    });
+    executor.block_on(async move {
+        handle.await.unwrap();
+        for task in busy_tasks {
+            task.await.unwrap();
+        }
+    });
+    assert!(CPUS.load(Ordering::Relaxed).count_ones() > 1);
+}
+
+#[test_case]
+fn wake_from_other_cpu() {
+    use kos::task::channel::oneshot;
+
+    let mut executor = Executor::new();
+    let spawner = executor.spawner();
+    let (tx, rx) = oneshot::channel();
+    // the receiver is pinned to this CPU's executor, the sender is likely
+    // stolen by an AP while the BSP halts
+    let receiver = executor.spawn(async move { rx.await.unwrap() });
+    let sender = spawner.spawn(async move {
+        busy(10);
+        tx.send(42).unwrap();
+    });
+    let value = executor.block_on(async move {
+        sender.await.unwrap();
+        receiver.await
+    });
+    assert_eq!(value.unwrap(), 42);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kos::test_panic_handler(info)
+}